@@ -1,11 +1,24 @@
+use std::path::Path;
 use std::process::Command;
 
 fn main() {
+    // compile and runtime take every path explicitly, so point both at the
+    // project's dataset and output directory
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).parent().expect("app sits inside the project");
+    let output = root.join("serialized_outputs");
+
     // Step 1: Run the preprocessor
-    let status = Command::new("cargo")
-        .args(["run", "-p", "compile"])
-        .status()
-        .expect("Failed to run compile");
+    let mut compile = Command::new("cargo");
+    compile
+        .args(["run", "-p", "compile", "--"])
+        .arg(root.join("Dataset/words.txt"))
+        .arg("--output")
+        .arg(&output);
+    let frequencies = root.join("Dataset/frequencies.txt");
+    if frequencies.exists() {
+        compile.arg("--frequencies").arg(frequencies);
+    }
+    let status = compile.status().expect("Failed to run compile");
 
     if !status.success() {
         panic!("Compile step failed");
@@ -13,7 +26,8 @@ fn main() {
 
     // Step 2: Run the runtime
     let status = Command::new("cargo")
-        .args(["run", "-p", "runtime"])
+        .args(["run", "-p", "runtime", "--"])
+        .arg(&output)
         .status()
         .expect("Failed to run runtime");
 
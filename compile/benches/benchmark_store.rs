@@ -3,37 +3,61 @@ use compile::{process_data, Trees, Scope};
 
 fn bench_trie_word(c: &mut Criterion) {
     c.bench_function("process_data - Trie + Word", |b| {
-        b.iter(|| process_data(Trees::Trie, Scope::Word))
+        b.iter(|| process_data(Trees::Trie, Scope::Word).unwrap())
     });
 }
 
 fn bench_trie_line(c: &mut Criterion) {
     c.bench_function("process_data - Trie + Line", |b| {
-        b.iter(|| process_data(Trees::Trie, Scope::Line))
+        b.iter(|| process_data(Trees::Trie, Scope::Line).unwrap())
+    });
+}
+
+fn bench_radix_word(c: &mut Criterion) {
+    c.bench_function("process_data - Radix + Word", |b| {
+        b.iter(|| process_data(Trees::Radix, Scope::Word).unwrap())
+    });
+}
+
+fn bench_radix_line(c: &mut Criterion) {
+    c.bench_function("process_data - Radix + Line", |b| {
+        b.iter(|| process_data(Trees::Radix, Scope::Line).unwrap())
     });
 }
 
 fn bench_suffix_word(c: &mut Criterion) {
     c.bench_function("process_data - Suffix + Word", |b| {
-        b.iter(|| process_data(Trees::Suffix, Scope::Word))
+        b.iter(|| process_data(Trees::Suffix, Scope::Word).unwrap())
     });
 }
 
 fn bench_suffix_line(c: &mut Criterion) {
     c.bench_function("process_data - Suffix + Line", |b| {
-        b.iter(|| process_data(Trees::Suffix, Scope::Line))
+        b.iter(|| process_data(Trees::Suffix, Scope::Line).unwrap())
+    });
+}
+
+fn bench_suffix_array_word(c: &mut Criterion) {
+    c.bench_function("process_data - SuffixArray + Word", |b| {
+        b.iter(|| process_data(Trees::SuffixArray, Scope::Word).unwrap())
+    });
+}
+
+fn bench_suffix_array_line(c: &mut Criterion) {
+    c.bench_function("process_data - SuffixArray + Line", |b| {
+        b.iter(|| process_data(Trees::SuffixArray, Scope::Line).unwrap())
     });
 }
 
 fn bench_ngram_word(c: &mut Criterion) {
     c.bench_function("process_data - NGram + Word", |b| {
-        b.iter(|| process_data(Trees::NGramIndex, Scope::Word))
+        b.iter(|| process_data(Trees::NGramIndex, Scope::Word).unwrap())
     });
 }
 
 fn bench_ngram_line(c: &mut Criterion) {
     c.bench_function("process_data - NGram + Line", |b| {
-        b.iter(|| process_data(Trees::NGramIndex, Scope::Line))
+        b.iter(|| process_data(Trees::NGramIndex, Scope::Line).unwrap())
     });
 }
 
@@ -41,8 +65,12 @@ criterion_group!(
     benches,
     bench_trie_word,
     bench_trie_line,
+    bench_radix_word,
+    bench_radix_line,
     bench_suffix_word,
     bench_suffix_line,
+    bench_suffix_array_word,
+    bench_suffix_array_line,
     bench_ngram_word,
     bench_ngram_line
 );
@@ -0,0 +1,30 @@
+use std::io;
+use std::path::PathBuf;
+use data_structs::error::SearchError;
+use thiserror::Error;
+
+// Why a build step failed, reported by `compile` instead of panicking
+#[derive(Debug, Error)]
+pub enum Errors {
+    #[error("could not determine the project root")]
+    ProjectRoot,
+    #[error("no input files found under {0:?}")]
+    NoInput(PathBuf),
+    #[error("I/O error on {path:?}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error(transparent)]
+    Index(#[from] SearchError),
+}
+
+impl Errors {
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        Errors::Io {
+            path: path.into(),
+            source,
+        }
+    }
+}
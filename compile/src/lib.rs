@@ -1,100 +1,374 @@
-use bincode::config;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::{
     fs::{self, File},
     io::Write,
 };
 use unicode_segmentation::UnicodeSegmentation;
 
+use data_structs::normalize::Normalization;
+use data_structs::strings::StringTable;
+use data_structs::structure::{encode_versioned, SearchStructure};
 use data_structs::trees;
+use trees::fst::FstBuilder;
 use trees::ngram::{NGramIndex, SearchScopeNgram};
+use trees::positional::PositionalIndex;
+use trees::radix::RadixTree;
 use trees::suffix::SuffixTree;
+use trees::suffix_array::SuffixArray;
+use trees::fm_index::{FmIndex, DEFAULT_SAMPLE_RATE};
 use trees::trie::Trie;
 
-//mod error;
-//use error::Errors;
+mod error;
+pub use error::Errors;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Trees {
     Trie,
+    Radix,
     Suffix,
+    SuffixArray,
+    FmIndex,
     NGramIndex,
+    Positional,
+    Fst,
 }
 
+impl Trees {
+    pub const ALL: [Trees; 8] = [
+        Trees::Trie,
+        Trees::Radix,
+        Trees::Suffix,
+        Trees::SuffixArray,
+        Trees::FmIndex,
+        Trees::NGramIndex,
+        Trees::Positional,
+        Trees::Fst,
+    ];
+
+    // What a build without an explicit list makes. The FM-index is left
+    // out: it answers the same queries as the suffix array, in less space
+    // but more slowly, so it is only built when asked for
+    pub const DEFAULT: [Trees; 7] = [
+        Trees::Trie,
+        Trees::Radix,
+        Trees::Suffix,
+        Trees::SuffixArray,
+        Trees::NGramIndex,
+        Trees::Positional,
+        Trees::Fst,
+    ];
+
+    // The name the index writes its file under, also used on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Trees::Trie => "trie",
+            Trees::Radix => "radix",
+            Trees::Suffix => "suffix",
+            Trees::SuffixArray => "suffix-array",
+            Trees::FmIndex => "fm-index",
+            Trees::NGramIndex => "ngram",
+            Trees::Positional => "positional",
+            Trees::Fst => "fst",
+        }
+    }
+
+    // Files the index is written to inside its scope directory
+    pub fn files(self) -> Vec<String> {
+        match self {
+            Trees::Fst => [trees::fst::FORWARD_FILE, trees::fst::REVERSE_FILE, trees::fst::POSTINGS_FILE, trees::fst::META_FILE]
+                .iter()
+                .map(|file| file.to_string())
+                .collect(),
+            tree => vec![format!("{}-serial.bin", tree.name())],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Scope {
     Word,
     Line,
 }
 
-pub fn process_data(trees: Trees, search_scope: Scope) {
-    let dataset_path = Path::new(env!("CARGO_MANIFEST_DIR"))
-        .parent()
-        .unwrap() // one level up to project root
-        .join("Dataset/words.txt");
-    let dataset = fs::read_to_string(dataset_path).unwrap();
-
-    let (chosen_scope, limit) = match search_scope {
-        Scope::Word => (dataset.unicode_words().collect::<Vec<&str>>(), 255),
-        Scope::Line => (dataset.lines().collect::<Vec<&str>>(), 32768),
-    };
-
-    let serialized_output = match trees {
-        Trees::Trie => {
-            let mut trie = Trie::new();
-            for token in chosen_scope.iter() {
-                if token.len() > limit {
-                    continue;
+impl Scope {
+    pub const ALL: [Scope; 2] = [Scope::Word, Scope::Line];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Scope::Word => "word",
+            Scope::Line => "line",
+        }
+    }
+
+    // Directory under the output root the scope's indexes go to
+    fn directory(self) -> &'static str {
+        match self {
+            Scope::Word => "word_scope",
+            Scope::Line => "line_scope",
+        }
+    }
+}
+
+// Longest token, in bytes, stored when no limit is given
+pub const DEFAULT_WORD_LIMIT: usize = 255;
+pub const DEFAULT_LINE_LIMIT: usize = 32768;
+
+#[derive(Default)]
+pub struct BuildOptions {
+    // `word<TAB>count` lines used to seed the Trie's ranking weights
+    pub frequency_list: Option<PathBuf>,
+    // Case policy recorded in every index so runtime folds queries the same way
+    pub normalization: Normalization,
+    // Every how many text positions the FM-index keeps a suffix array
+    // entry, `DEFAULT_SAMPLE_RATE` when unset
+    pub fm_sample_rate: Option<usize>,
+    // Gram sizes of the n-gram index, e.g. [1, 2, 3]; empty means
+    // `DEFAULT_GRAM_SIZES`
+    pub gram_sizes: Vec<usize>,
+    // Text files or directories (read recursively) to index; empty means
+    // `Dataset/words.txt` in the project
+    pub inputs: Vec<PathBuf>,
+    // Where the `word_scope` and `line_scope` directories are written,
+    // `serialized_outputs` in the project when unset
+    pub output_dir: Option<PathBuf>,
+    // Longest word and line stored, in bytes; longer tokens are skipped.
+    // `DEFAULT_WORD_LIMIT` and `DEFAULT_LINE_LIMIT` when unset
+    pub word_limit: Option<usize>,
+    pub line_limit: Option<usize>,
+}
+
+impl BuildOptions {
+    pub fn limit(&self, scope: Scope) -> usize {
+        match scope {
+            Scope::Word => self.word_limit.unwrap_or(DEFAULT_WORD_LIMIT),
+            Scope::Line => self.line_limit.unwrap_or(DEFAULT_LINE_LIMIT),
+        }
+    }
+
+    // The files to read, directories expanded in path order
+    pub fn input_files(&self) -> Result<Vec<PathBuf>, Errors> {
+        if self.inputs.is_empty() {
+            return Ok(vec![project_root()?.join("Dataset/words.txt")]);
+        }
+        let mut files = Vec::new();
+        for input in self.inputs.iter() {
+            if input.is_dir() {
+                let found = files.len();
+                collect_files(input, &mut files)?;
+                if files.len() == found {
+                    return Err(Errors::NoInput(input.clone()));
                 }
-                trie.store(token.to_string());
+            } else {
+                files.push(input.clone());
             }
-            bincode::encode_to_vec(trie, config::standard()).unwrap()
         }
-        Trees::Suffix => {
-            let mut suffix = SuffixTree::new();
-            for token in chosen_scope.iter() {
-                if token.len() > limit {
-                    continue;
+        Ok(files)
+    }
+
+    pub fn scope_dir(&self, scope: Scope) -> Result<PathBuf, Errors> {
+        let output_dir = match &self.output_dir {
+            Some(output_dir) => output_dir.clone(),
+            None => project_root()?.join("serialized_outputs"),
+        };
+        Ok(output_dir.join(scope.directory()))
+    }
+}
+
+// What a build of one scope would read and write, without building it
+pub struct BuildPlan {
+    pub inputs: Vec<PathBuf>,
+    pub scope_dir: PathBuf,
+    pub limit: usize,
+    pub tokens: usize,   // tokens that fit the limit and would be stored
+    pub skipped: usize,  // tokens over the limit
+}
+
+pub fn plan_build(search_scope: Scope, options: &BuildOptions) -> Result<BuildPlan, Errors> {
+    let inputs = options.input_files()?;
+    let dataset = read_inputs(&inputs)?;
+    let limit = options.limit(search_scope);
+    let tokens = tokens_of(&dataset, search_scope);
+    let skipped = tokens.iter().filter(|token| token.len() > limit).count();
+
+    Ok(BuildPlan {
+        inputs,
+        scope_dir: options.scope_dir(search_scope)?,
+        limit,
+        tokens: tokens.len() - skipped,
+        skipped,
+    })
+}
+
+pub fn process_data(trees: Trees, search_scope: Scope) -> Result<(), Errors> {
+    process_data_with(trees, search_scope, &BuildOptions::default())
+}
+
+pub fn process_data_with(trees: Trees, search_scope: Scope, options: &BuildOptions) -> Result<(), Errors> {
+    build_scope(&[trees], search_scope, options)
+}
+
+// Reads and tokenizes the inputs once, then builds every tree in `trees`
+// over one string table, so their ids always agree with the table written
+// next to them
+pub fn build_scope(trees: &[Trees], search_scope: Scope, options: &BuildOptions) -> Result<(), Errors> {
+    let dataset = read_inputs(&options.input_files()?)?;
+    let limit = options.limit(search_scope);
+    let chosen_scope: Vec<&str> = tokens_of(&dataset, search_scope)
+        .into_iter()
+        .filter(|token| token.len() <= limit)
+        .collect();
+
+    let scope_path = search_scope.directory();
+    let scope_dir = options.scope_dir(search_scope)?;
+    fs::create_dir_all(&scope_dir).map_err(|e| Errors::io(&scope_dir, e))?;
+
+    // Interned up front so every index is stamped with the final table;
+    // the trees intern the same tokens and leave it unchanged
+    let mut strings = StringTable::new();
+    for token in chosen_scope.iter() {
+        strings.intern(token);
+    }
+    let table = strings.checksum();
+
+    for &tree in trees.iter() {
+        match tree {
+            Trees::Trie => {
+                let mut trie = fill(Trie::with_normalization(options.normalization), &chosen_scope, &mut strings);
+                if let Some(path) = &options.frequency_list {
+                    seed_frequencies(&mut trie, path)?;
                 }
-                suffix.store(token.to_string());
+                write_structure(&trie, table, &scope_dir, scope_path)?;
             }
-            bincode::encode_to_vec(suffix, config::standard()).unwrap()
-        }
-        Trees::NGramIndex => {
-            let mut ngram = NGramIndex::new();
-            if let Scope::Line = search_scope {
-                ngram.search_type = SearchScopeNgram::Lines;
+            Trees::Radix => {
+                let radix = fill(RadixTree::with_normalization(options.normalization), &chosen_scope, &mut strings);
+                write_structure(&radix, table, &scope_dir, scope_path)?;
             }
-            for token in chosen_scope.iter() {
-                if token.len() > limit {
-                    continue;
+            Trees::Suffix => {
+                let suffix = fill(SuffixTree::with_normalization(options.normalization), &chosen_scope, &mut strings);
+                write_structure(&suffix, table, &scope_dir, scope_path)?;
+            }
+            Trees::SuffixArray => {
+                let suffix_array = fill(SuffixArray::with_normalization(options.normalization), &chosen_scope, &mut strings);
+                write_structure(&suffix_array, table, &scope_dir, scope_path)?;
+            }
+            Trees::FmIndex => {
+                let sample_rate = options.fm_sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE);
+                let fm_index = fill(FmIndex::with_sample_rate(options.normalization, sample_rate), &chosen_scope, &mut strings);
+                write_structure(&fm_index, table, &scope_dir, scope_path)?;
+            }
+            Trees::NGramIndex => {
+                let mut ngram = NGramIndex::with_gram_sizes(options.normalization, &options.gram_sizes);
+                if let Scope::Line = search_scope {
+                    ngram.search_type = SearchScopeNgram::Lines;
                 }
-                ngram.store(token.to_string());
+                let ngram = fill(ngram, &chosen_scope, &mut strings);
+                write_structure(&ngram, table, &scope_dir, scope_path)?;
+            }
+            Trees::Positional => {
+                let positional = fill(PositionalIndex::with_normalization(options.normalization), &chosen_scope, &mut strings);
+                write_structure(&positional, table, &scope_dir, scope_path)?;
+            }
+            // Written as several files meant to be memory-mapped, not one bincode blob
+            Trees::Fst => {
+                let mut fst = FstBuilder::with_normalization(options.normalization);
+                for token in chosen_scope.iter() {
+                    fst.store(token.to_string(), &mut strings);
+                }
+                fst.write(&scope_dir, table)?;
             }
-            bincode::encode_to_vec(ngram, config::standard()).unwrap()
         }
-    };
-
-    let scope_path = match search_scope {
-        Scope::Word => "word_scope",
-        Scope::Line => "line_scope",
-    };
-
-    let type_path = match trees {
-        Trees::Trie => "trie-serial.bin",
-        Trees::Suffix => "suffix-serial.bin",
-        Trees::NGramIndex => "ngram-serial.bin",
-    };
-
-    let output_path = Path::new(env!("CARGO_MANIFEST_DIR"))
-        .parent()
-        .unwrap()
-        .join("serialized_outputs")
-        .join(scope_path)
-        .join(type_path);
-
-    if let Some(parent) = output_path.parent() {
-        fs::create_dir_all(parent).unwrap();
     }
 
-    let mut serialized_file = File::create(output_path).unwrap();
-    serialized_file.write_all(&serialized_output).unwrap();
+    let serialized_strings = encode_versioned(&strings, table)?;
+    write_file(&scope_dir.join("strings-serial.bin"), &serialized_strings)
+}
+
+fn project_root() -> Result<PathBuf, Errors> {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent() // one level up to project root
+        .map(Path::to_path_buf)
+        .ok_or(Errors::ProjectRoot)
+}
+
+// Every file below `dir`, sorted so builds are reproducible
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Errors> {
+    let mut entries = fs::read_dir(dir)
+        .and_then(|entries| entries.map(|entry| entry.map(|entry| entry.path())).collect::<Result<Vec<_>, _>>())
+        .map_err(|e| Errors::io(dir, e))?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+// The inputs one after another, each ending on a line break so the last
+// line of one file never runs into the first of the next
+fn read_inputs(inputs: &[PathBuf]) -> Result<String, Errors> {
+    let mut dataset = String::new();
+    for path in inputs.iter() {
+        dataset.push_str(&fs::read_to_string(path).map_err(|e| Errors::io(path, e))?);
+        if !dataset.is_empty() && !dataset.ends_with('\n') {
+            dataset.push('\n');
+        }
+    }
+    Ok(dataset)
+}
+
+fn tokens_of(dataset: &str, search_scope: Scope) -> Vec<&str> {
+    match search_scope {
+        Scope::Word => dataset.unicode_words().collect(),
+        Scope::Line => dataset.lines().collect(),
+    }
+}
+
+fn write_file(path: &Path, contents: &[u8]) -> Result<(), Errors> {
+    let mut file = File::create(path).map_err(|e| Errors::io(path, e))?;
+    file.write_all(contents).map_err(|e| Errors::io(path, e))
+}
+
+// Stores every token, then finishes the build
+fn fill<T: SearchStructure>(mut structure: T, tokens: &[&str], strings: &mut StringTable) -> T {
+    for token in tokens.iter() {
+        structure.store(token.to_string(), strings);
+    }
+    structure.finish();
+    structure
+}
+
+// Writes `<name>-serial.bin`, the file runtime looks the structure up by
+fn write_structure<T: SearchStructure>(structure: &T, table: u64, scope_dir: &Path, scope_path: &str) -> Result<(), Errors> {
+    let serialized_output = structure.encode_to_vec(table)?;
+    write_file(&scope_dir.join(format!("{}-serial.bin", structure.name())), &serialized_output)?;
+
+    let stats = structure.stats();
+    eprintln!(
+        "{} {}: {} keys, {} postings, {} nodes, {} bytes",
+        scope_path,
+        structure.name(),
+        stats.keys,
+        stats.postings,
+        stats.nodes,
+        serialized_output.len()
+    );
+    Ok(())
+}
+
+fn seed_frequencies(trie: &mut Trie, path: &Path) -> Result<(), Errors> {
+    let frequencies = fs::read_to_string(path).map_err(|e| Errors::io(path, e))?;
+
+    for line in frequencies.lines() {
+        let Some((word, count)) = line.split_once('\t') else {
+            continue;
+        };
+        // Skip malformed counts rather than aborting the whole build
+        if let Ok(count) = count.trim().parse::<u32>() {
+            trie.add_weight(word.trim(), count);
+        }
+    }
+    Ok(())
 }
@@ -1,17 +1,163 @@
+use std::path::PathBuf;
+use std::process::ExitCode;
 use std::time::Instant;
-use compile::{ process_data, Trees, Scope};
+use clap::Parser;
+use data_structs::normalize::Normalization;
+use data_structs::trees::fm_index::DEFAULT_SAMPLE_RATE;
+use compile::{build_scope, plan_build, BuildOptions, Errors, Trees, Scope, DEFAULT_LINE_LIMIT, DEFAULT_WORD_LIMIT};
 
-fn main() {
+// Paths are taken as given, relative ones from the current directory, so an
+// installed binary never reads or writes inside its build tree
+#[derive(Parser)]
+#[command(name = "compile", about = "Builds the search indexes for a dataset")]
+struct Args {
+    #[arg(value_name = "INPUT", required = true, help = "Text files or directories (read recursively) to index")]
+    inputs: Vec<PathBuf>,
+
+    #[arg(short, long, value_name = "DIR", default_value = "serialized_outputs", help = "Where the word_scope and line_scope directories are written")]
+    output: PathBuf,
+
+    #[arg(
+        short,
+        long = "index",
+        value_name = "INDEX",
+        value_delimiter = ',',
+        value_parser = parse_tree,
+        help = "Indexes to build: trie, radix, suffix, suffix-array, fm-index, ngram, positional or fst [default: all but fm-index]"
+    )]
+    indexes: Vec<Trees>,
+
+    #[arg(short, long = "scope", value_name = "SCOPE", value_delimiter = ',', value_parser = parse_scope, help = "Scopes to build: word or line [default: both]")]
+    scopes: Vec<Scope>,
+
+    #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_WORD_LIMIT, help = "Longest word stored, longer ones are skipped")]
+    max_word_len: usize,
+
+    #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_LINE_LIMIT, help = "Longest line stored, longer ones are skipped")]
+    max_line_len: usize,
+
+    #[arg(
+        short,
+        long,
+        value_name = "POLICY",
+        default_value = "ascii-fold",
+        value_parser = parse_normalization,
+        help = "Case folding of keys and queries: case-sensitive, ascii-fold or unicode-fold"
+    )]
+    normalization: Normalization,
+
+    #[arg(long, value_name = "SIZES", value_delimiter = ',', value_parser = parse_positive, help = "Gram sizes of the n-gram index, e.g. 1,2,3 [default: 2]")]
+    gram_sizes: Vec<usize>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = DEFAULT_SAMPLE_RATE,
+        value_parser = parse_positive,
+        help = "Every how many text positions the FM-index keeps a suffix array entry; lower locates faster but uses more space"
+    )]
+    fm_sample_rate: usize,
+
+    #[arg(long, value_name = "FILE", help = "word<TAB>count list seeding the trie's ranking weights")]
+    frequencies: Option<PathBuf>,
+
+    #[arg(long, help = "Print what would be read and written without building anything")]
+    dry_run: bool,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let dry_run = args.dry_run;
     let now = Instant::now();
-    process_data(Trees::Trie, Scope::Line);
-    process_data(Trees::Trie, Scope::Word);
+    if let Err(e) = build(args) {
+        eprintln!("Failed to process document - {}", e);
+        return ExitCode::FAILURE;
+    }
+
+    if !dry_run {
+        let time_taken = now.elapsed().as_secs_f32();
+        eprintln!("Time taken to process document - {}", time_taken);
+    }
+    ExitCode::SUCCESS
+}
+
+fn parse_tree(name: &str) -> Result<Trees, String> {
+    Trees::ALL.into_iter().find(|tree| tree.name() == name).ok_or_else(|| {
+        let names: Vec<&str> = Trees::ALL.iter().map(|tree| tree.name()).collect();
+        format!("expected one of {}", names.join(", "))
+    })
+}
+
+fn parse_normalization(name: &str) -> Result<Normalization, String> {
+    Normalization::ALL.into_iter().find(|normalization| normalization.name() == name).ok_or_else(|| {
+        let names: Vec<&str> = Normalization::ALL.iter().map(|normalization| normalization.name()).collect();
+        format!("expected one of {}", names.join(", "))
+    })
+}
+
+fn parse_positive(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        Ok(value) => Ok(value),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn parse_scope(name: &str) -> Result<Scope, String> {
+    Scope::ALL.into_iter().find(|scope| scope.name() == name).ok_or_else(|| "expected word or line".to_string())
+}
+
+fn build(args: Args) -> Result<(), Errors> {
+    let options = BuildOptions {
+        frequency_list: args.frequencies,
+        normalization: args.normalization,
+        fm_sample_rate: Some(args.fm_sample_rate),
+        gram_sizes: args.gram_sizes,
+        inputs: args.inputs,
+        output_dir: Some(args.output),
+        word_limit: Some(args.max_word_len),
+        line_limit: Some(args.max_line_len),
+    };
+
+    let indexes = if args.indexes.is_empty() { Trees::DEFAULT.to_vec() } else { args.indexes };
+    let scopes = if args.scopes.is_empty() { vec![Scope::Line, Scope::Word] } else { args.scopes };
+    // Word positions only mean something inside lines
+    let builds = |scope: Scope| {
+        indexes
+            .iter()
+            .copied()
+            .filter(move |tree| *tree != Trees::Positional || scope == Scope::Line)
+    };
 
-    process_data(Trees::Suffix, Scope::Line);
-    process_data(Trees::Suffix, Scope::Word);
+    if args.dry_run {
+        for &scope in scopes.iter() {
+            let plan = plan_build(scope, &options)?;
+            println!(
+                "{} scope: {} tokens from {} file(s), {} over {} bytes skipped",
+                scope.name(),
+                plan.tokens,
+                plan.inputs.len(),
+                plan.skipped,
+                plan.limit
+            );
+            for input in plan.inputs.iter() {
+                println!("  read {}", input.display());
+            }
+            for tree in builds(scope) {
+                for file in tree.files() {
+                    println!("  {:<12} -> {}", tree.name(), plan.scope_dir.join(file).display());
+                }
+            }
+            println!("  {:<12} -> {}", "strings", plan.scope_dir.join("strings-serial.bin").display());
+        }
+        return Ok(());
+    }
 
-    process_data(Trees::NGramIndex, Scope::Line);
-    process_data(Trees::NGramIndex, Scope::Word);
-    
-    let time_taken = now.elapsed().as_secs_f32();
-    eprintln!("Time taken to process document - {}", time_taken);
+    for &scope in scopes.iter() {
+        let trees: Vec<Trees> = builds(scope).collect();
+        if !trees.is_empty() {
+            build_scope(&trees, scope, &options)?;
+        }
+    }
+    Ok(())
 }
@@ -0,0 +1,37 @@
+use std::io;
+use std::path::PathBuf;
+use bincode::error::{DecodeError, EncodeError};
+use thiserror::Error;
+
+// Everything an index can fail with, so callers can tell an empty result
+// apart from a corrupt or outdated file
+#[derive(Debug, Error)]
+pub enum SearchError {
+    #[error("no match for '{0}'")]
+    NoMatch(String),
+    #[error("invalid query: {0}")]
+    InvalidQuery(String),
+    #[error("I/O error on {path:?}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("failed to encode index: {0}")]
+    Encode(#[from] EncodeError),
+    #[error("failed to decode index: {0}")]
+    Decode(#[from] DecodeError),
+    #[error("index format version {found} does not match {expected}, rebuild the indexes")]
+    VersionMismatch { expected: u32, found: u32 },
+    #[error("corrupt fst: {0}")]
+    Fst(#[from] fst::Error),
+}
+
+impl SearchError {
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        SearchError::Io {
+            path: path.into(),
+            source,
+        }
+    }
+}
@@ -0,0 +1,49 @@
+// Test data shared by the unit tests of several structures
+
+// Linear congruential generator, so every run sees the same data
+pub(crate) struct Lcg(u32);
+
+impl Lcg {
+    pub(crate) fn new(seed: u32) -> Self {
+        Self(seed)
+    }
+
+    // Uniform enough below `bound` for test data, from the high bits
+    pub(crate) fn below(&mut self, bound: u32) -> u32 {
+        self.0 = self.0.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        (self.0 >> 16) % bound
+    }
+}
+
+// Strings over a small alphabet so most patterns occur many times,
+// with repeats, mixed case and a multi-byte char
+pub(crate) fn corpus(seed: u32) -> Vec<String> {
+    let mut random = Lcg::new(seed);
+    let alphabet = ['a', 'b', 'n', 'A', 'é'];
+    let mut strings: Vec<String> = (0..300)
+        .map(|_| (0..1 + random.below(8)).map(|_| alphabet[random.below(5) as usize]).collect())
+        .collect();
+    strings.extend(["banana", "banana", "bandana"].map(String::from));
+    strings
+}
+
+// Every pattern of up to three chars over the corpus alphabet, and a few
+// longer, mixed case and absent ones
+pub(crate) fn patterns() -> Vec<String> {
+    let mut patterns = vec![String::new()];
+    for _ in 0..3 {
+        let longer: Vec<String> = patterns
+            .iter()
+            .flat_map(|pattern| ['a', 'b', 'n', 'é'].map(|char| format!("{}{}", pattern, char)))
+            .collect();
+        patterns.extend(longer);
+    }
+    patterns.retain(|pattern| !pattern.is_empty());
+    patterns.extend(["banana", "ANA", "xyz"].map(String::from));
+    patterns
+}
+
+// Byte offsets of every occurrence of `pattern` in `text`, overlaps included
+pub(crate) fn naive(text: &str, pattern: &str) -> Vec<usize> {
+    (0..text.len()).filter(|&offset| text.as_bytes()[offset..].starts_with(pattern.as_bytes())).collect()
+}
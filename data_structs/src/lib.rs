@@ -1 +1,11 @@
 pub mod trees;
+pub mod strings;
+pub mod normalize;
+pub mod structure;
+pub mod error;
+pub mod wildcard;
+pub mod prefilter;
+pub mod postings;
+
+#[cfg(test)]
+mod fixtures;
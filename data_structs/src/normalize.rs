@@ -0,0 +1,34 @@
+use std::borrow::Cow;
+use bincode::{Encode,Decode};
+use unicase::UniCase;
+
+// How keys and queries are folded before they touch an index. Every index
+// records the policy it was built with and applies the same one to queries
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Normalization {
+    CaseSensitive,
+    #[default]
+    AsciiFold,    // lowercase A-Z only, everything else kept as is
+    UnicodeFold,  // full Unicode case folding, e.g. "Straße" == "strasse"
+}
+
+impl Normalization {
+    pub const ALL: [Normalization; 3] = [Normalization::CaseSensitive, Normalization::AsciiFold, Normalization::UnicodeFold];
+
+    pub fn apply<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        match self {
+            Normalization::CaseSensitive => Cow::Borrowed(text),
+            Normalization::AsciiFold if !text.bytes().any(|byte| byte.is_ascii_uppercase()) => Cow::Borrowed(text),
+            Normalization::AsciiFold => Cow::Owned(text.to_ascii_lowercase()),
+            Normalization::UnicodeFold => Cow::Owned(UniCase::new(text).to_folded_case()),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Normalization::CaseSensitive => "case-sensitive",
+            Normalization::AsciiFold => "ascii-fold",
+            Normalization::UnicodeFold => "unicode-fold",
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use bincode::{Decode, Encode};
+use crate::strings::DocId;
+
+// Ids per block; a skip pointer marks the start of each block after the
+// first, so short lists carry none
+const BLOCK: usize = 64;
+
+// A sorted set of ids stored as variable-byte deltas, with a skip pointer
+// per block so lookups and intersections can jump over whole blocks
+// instead of decoding them
+#[derive(Clone, Debug, Default, Encode, Decode)]
+pub struct PostingList {
+    bytes: Vec<u8>,             // delta from the previous id, 7 bits per byte, high bit = more
+    skips: Vec<(DocId, u32)>,   // first id of blocks 1.. and the byte each starts at
+}
+
+impl PostingList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        let (_, in_last_block) = self.tail();
+        self.skips.len() * BLOCK + in_last_block
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn iter(&self) -> Postings<'_> {
+        Postings {
+            list: self,
+            block: 0,
+            offset: 0,
+            seen: 0,
+            previous: 0,
+        }
+    }
+
+    // The largest id and how many ids the last block holds. Neither is
+    // stored, decoding one block is cheap enough
+    fn tail(&self) -> (DocId, usize) {
+        let mut ids = self.iter();
+        ids.seek(self.skips.last().map_or(0, |&(first, _)| first));
+        ids.fold((0, 0), |(_, count), id| (id, count + 1))
+    }
+
+    pub fn contains(&self, id: DocId) -> bool {
+        self.iter().seek(id) == Some(id)
+    }
+
+    // Adds `id` if missing. Ids above every stored one, the usual case while
+    // building, are appended; anything else re-encodes the list
+    pub fn insert(&mut self, id: DocId) {
+        if self.is_empty() || id > self.tail().0 {
+            self.push(id);
+        } else if !self.contains(id) {
+            let mut ids: Vec<DocId> = self.iter().collect();
+            let position = ids.partition_point(|&other| other < id);
+            ids.insert(position, id);
+            *self = ids.into_iter().collect();
+        }
+    }
+
+    pub fn remove(&mut self, id: DocId) -> bool {
+        if !self.contains(id) {
+            return false;
+        }
+        *self = self.iter().filter(|&other| other != id).collect();
+        true
+    }
+
+    fn push(&mut self, id: DocId) {
+        let (last, in_last_block) = self.tail();
+        let delta = if self.is_empty() { id } else { id - last };
+        if in_last_block == BLOCK {
+            self.skips.push((id, self.bytes.len() as u32));
+        }
+        write_varint(&mut self.bytes, delta);
+    }
+}
+
+// Expects ids in increasing order
+impl FromIterator<DocId> for PostingList {
+    fn from_iter<I: IntoIterator<Item = DocId>>(ids: I) -> Self {
+        let mut list = PostingList::new();
+        for id in ids {
+            list.push(id);
+        }
+        list
+    }
+}
+
+// Decodes a list front to back; `seek` can jump ahead by whole blocks
+pub struct Postings<'a> {
+    list: &'a PostingList,
+    block: usize,    // block holding the next id
+    offset: usize,   // byte of the next id
+    seen: usize,     // ids decoded so far
+    previous: DocId, // last id decoded, the base of the next delta
+}
+
+impl Postings<'_> {
+    // Moves to the first id not below `target` and returns it without
+    // consuming it. Skip pointers are galloped over to find the block,
+    // so at most one block is decoded
+    pub fn seek(&mut self, target: DocId) -> Option<DocId> {
+        // skips[b - 1] starts block b, so these are the blocks after this one
+        let ahead = &self.list.skips[self.block.min(self.list.skips.len())..];
+        let mut bound = 1;
+        while bound <= ahead.len() && ahead[bound - 1].0 <= target {
+            bound *= 2;
+        }
+        let start = bound / 2;
+        let end = bound.min(ahead.len());
+        let jump = start + ahead[start..end].partition_point(|&(first, _)| first <= target);
+        if jump > 0 {
+            self.block += jump;
+            self.offset = self.list.skips[self.block - 1].1 as usize;
+            self.seen = self.block * BLOCK;
+            self.previous = self.previous_of(self.block);
+        }
+
+        loop {
+            let id = self.peek()?;
+            if id >= target {
+                return Some(id);
+            }
+            self.next();
+        }
+    }
+
+    fn peek(&self) -> Option<DocId> {
+        if self.offset >= self.list.bytes.len() {
+            return None;
+        }
+        let (delta, _) = read_varint(&self.list.bytes, self.offset);
+        Some(if self.seen == 0 { delta } else { self.previous + delta })
+    }
+
+    // The id just before `block`, which its first delta is measured from
+    fn previous_of(&self, block: usize) -> DocId {
+        let (first, offset) = self.list.skips[block - 1];
+        let (delta, _) = read_varint(&self.list.bytes, offset as usize);
+        first - delta
+    }
+}
+
+impl Iterator for Postings<'_> {
+    type Item = DocId;
+
+    fn next(&mut self) -> Option<DocId> {
+        let id = self.peek()?;
+        let (_, next) = read_varint(&self.list.bytes, self.offset);
+        self.offset = next;
+        self.seen += 1;
+        self.previous = id;
+        if self.seen.is_multiple_of(BLOCK) {
+            self.block += 1;
+        }
+        Some(id)
+    }
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        bytes.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+// The value at `offset` and the offset just after it
+fn read_varint(bytes: &[u8], mut offset: usize) -> (u32, usize) {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[offset];
+        offset += 1;
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return (value, offset);
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::Lcg;
+
+    // Ids spread over several blocks, with gaps wide enough to need
+    // multi-byte deltas
+    fn ids(count: u32) -> Vec<DocId> {
+        (0..count).map(|index| index * 3 + (index / 50) * 1_000).collect()
+    }
+
+    #[test]
+    fn round_trips_and_reports_its_length() {
+        for count in [0, 1, BLOCK as u32 - 1, BLOCK as u32, BLOCK as u32 + 1, 5 * BLOCK as u32 + 7] {
+            let ids = ids(count);
+            let list: PostingList = ids.iter().copied().collect();
+            assert_eq!(list.iter().collect::<Vec<_>>(), ids);
+            assert_eq!(list.len(), ids.len());
+            assert_eq!(list.is_empty(), ids.is_empty());
+        }
+    }
+
+    #[test]
+    fn seek_finds_the_first_id_not_below_the_target_across_blocks() {
+        let ids = ids(5 * BLOCK as u32 + 7);
+        let list: PostingList = ids.iter().copied().collect();
+        let last = *ids.last().unwrap();
+
+        // From a fresh cursor every time
+        for target in 0..=last + 1 {
+            let expected = ids.iter().copied().find(|&id| id >= target);
+            assert_eq!(list.iter().seek(target), expected, "seek to {}", target);
+        }
+
+        // And on one cursor moving forward, in steps that land on, before
+        // and past block starts
+        for step in [1, 7, BLOCK as u32 * 3 - 1, 1_500] {
+            let mut cursor = list.iter();
+            let mut target = 0;
+            while target <= last + step {
+                let expected = ids.iter().copied().find(|&id| id >= target);
+                assert_eq!(cursor.seek(target), expected, "seek to {} in steps of {}", target, step);
+                target += step;
+            }
+        }
+
+        // Seeking does not consume, the id found comes next
+        let mut cursor = list.iter();
+        let found = cursor.seek(ids[BLOCK * 2]);
+        assert_eq!(cursor.next(), found);
+        assert_eq!(cursor.next(), Some(ids[BLOCK * 2 + 1]));
+    }
+
+    #[test]
+    fn insert_and_remove_keep_the_list_sorted() {
+        let mut reference: Vec<DocId> = Vec::new();
+        let mut list = PostingList::new();
+        let mut random = Lcg::new(3);
+        for _ in 0..2_000 {
+            let id = random.below(800);
+            if random.below(5) == 0 {
+                let at = reference.binary_search(&id);
+                assert_eq!(list.remove(id), at.is_ok(), "remove {}", id);
+                if let Ok(at) = at {
+                    reference.remove(at);
+                }
+            } else {
+                // Appends as well as inserts before stored ids
+                list.insert(id);
+                if let Err(at) = reference.binary_search(&id) {
+                    reference.insert(at, id);
+                }
+            }
+            assert_eq!(list.len(), reference.len());
+        }
+
+        assert_eq!(list.iter().collect::<Vec<_>>(), reference);
+        assert!(reference.len() > BLOCK * 2, "the list should span several blocks");
+        for id in 0..800 {
+            assert_eq!(list.contains(id), reference.binary_search(&id).is_ok(), "contains {}", id);
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use std::collections::HashSet;
+use regex_syntax::hir::{Hir, HirKind};
+use crate::strings::DocId;
+
+// Literal fragments every match of a regex must contain. An index can look
+// them up to narrow the candidates before the regex itself runs.
+// Alternations and classes are skipped, so `(?i)` or `foo|bar` yield nothing
+pub fn required_literals(pattern: &str) -> Vec<String> {
+    let Ok(hir) = regex_syntax::parse(pattern) else {
+        return Vec::new();
+    };
+    let mut literals = Vec::new();
+    collect(&hir, &mut literals);
+    literals
+}
+
+// Ids holding every literal, each looked up with `holding`, in increasing
+// order. `None` when there is no literal to look up
+pub fn candidates<I>(literals: &[String], holding: impl Fn(&str) -> I) -> Option<Vec<DocId>>
+where
+    I: Iterator<Item = DocId>,
+{
+    let mut sets: Vec<HashSet<DocId>> = literals
+        .iter()
+        .filter(|literal| !literal.is_empty())
+        .map(|literal| holding(literal).collect())
+        .collect();
+    sets.sort_unstable_by_key(HashSet::len);
+    let (smallest, rest) = sets.split_first()?;
+
+    let mut ids: Vec<DocId> = smallest.iter().copied().filter(|id| rest.iter().all(|set| set.contains(id))).collect();
+    ids.sort_unstable();
+    Some(ids)
+}
+
+fn collect(hir: &Hir, literals: &mut Vec<String>) {
+    match hir.kind() {
+        HirKind::Literal(literal) => {
+            if let Ok(text) = std::str::from_utf8(&literal.0) {
+                literals.push(text.to_string());
+            }
+        }
+        HirKind::Capture(capture) => collect(&capture.sub, literals),
+        HirKind::Repetition(repetition) if repetition.min > 0 => collect(&repetition.sub, literals),
+        HirKind::Concat(subs) => {
+            for sub in subs.iter() {
+                collect(sub, literals);
+            }
+        }
+        _ => {}
+    }
+}
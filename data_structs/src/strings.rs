@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use bincode::de::{BorrowDecoder, Decoder};
+use bincode::enc::Encoder;
+use bincode::error::{DecodeError, EncodeError};
+use bincode::{BorrowDecode, Decode, Encode};
+
+// Id of an entry in a `StringTable`
+pub type DocId = u32;
+
+// Deduplicated text shared by every index of a scope. The indexes only keep
+// `DocId`s and results are resolved back to text at query time
+#[derive(Clone, Debug, Default)]
+pub struct StringTable {
+    strings: Vec<String>,
+    lookup: OnceLock<HashMap<String, DocId>>,  // only built when ids are looked up by text
+}
+
+impl StringTable {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&mut self, key: &str) -> DocId {
+        self.lookup();
+        let strings = &mut self.strings;
+        let lookup = self.lookup.get_mut().expect("lookup was just built");
+
+        *lookup.entry(key.to_string()).or_insert_with(|| {
+            strings.push(key.to_string());
+            (strings.len() - 1) as DocId
+        })
+    }
+
+    pub fn id_of(&self, key: &str) -> Option<DocId> {
+        self.lookup().get(key).copied()
+    }
+
+    pub fn resolve(&self, id: DocId) -> Option<&str> {
+        self.strings.get(id as usize).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+
+    // FNV-1a over every string in id order. Indexes are stamped with it so
+    // one built against another table is caught at load time
+    pub fn checksum(&self) -> u64 {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for string in self.strings.iter() {
+            // 0xff never occurs in UTF-8, so it cannot be confused with text
+            for &byte in string.as_bytes().iter().chain([0xff].iter()) {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+        }
+        hash
+    }
+
+    fn lookup(&self) -> &HashMap<String, DocId> {
+        self.lookup.get_or_init(|| {
+            self.strings
+                .iter()
+                .enumerate()
+                .map(|(id, string)| (string.clone(), id as DocId))
+                .collect()
+        })
+    }
+}
+
+// Only the strings are serialized, the lookup is rebuilt on demand
+impl Encode for StringTable {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.strings.encode(encoder)
+    }
+}
+
+impl<Context> Decode<Context> for StringTable {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        Ok(Self {
+            strings: Vec::decode(decoder)?,
+            lookup: OnceLock::new(),
+        })
+    }
+}
+
+impl<'de, Context> BorrowDecode<'de, Context> for StringTable {
+    fn borrow_decode<D: BorrowDecoder<'de, Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        Ok(Self {
+            strings: Vec::borrow_decode(decoder)?,
+            lookup: OnceLock::new(),
+        })
+    }
+}
@@ -0,0 +1,212 @@
+use std::ops::Bound;
+use bincode::{config, Decode, Encode};
+use regex::Regex;
+use crate::error::SearchError;
+use crate::normalize::Normalization;
+use crate::strings::{DocId, StringTable};
+
+// A query as the runtime hands it to an index. Indexes answer the kinds
+// they support and return `None` for the rest
+#[derive(Debug, Clone, Copy)]
+pub enum Query<'q> {
+    Prefix(&'q str),
+    Suffix(&'q str),
+    Contains(&'q str, ContainsMode),
+    Fuzzy { term: &'q str, max_typos: usize },
+    // Keys between the bounds, yielded in sorted order
+    Range { start: Bound<&'q str>, end: Bound<&'q str> },
+    // The nearest key strictly after or before the given one
+    Successor(&'q str),
+    Predecessor(&'q str),
+    // Whole keys matching a `?`/`*` pattern, see `wildcard::Pattern`
+    Wildcard(&'q str),
+    // Keys the regex matches anywhere in their original text
+    Regex(&'q Regex),
+}
+
+// Which strings holding the key a contains query returns
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ContainsMode {
+    #[default]
+    Anywhere,     // every string holding the key, at its start and end included
+    StrictInfix,  // only strings where no occurrence touches either end
+}
+
+impl ContainsMode {
+    // For debug messages and result headers
+    pub fn name(self) -> &'static str {
+        match self {
+            ContainsMode::Anywhere => "anywhere",
+            ContainsMode::StrictInfix => "strict infix",
+        }
+    }
+
+    // Whether a string whose matches touch its start or end still counts
+    pub fn allows_ends(self) -> bool {
+        self == ContainsMode::Anywhere
+    }
+}
+
+// How alike two gram sets are, from 0 (nothing shared) to 1 (the same)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Similarity {
+    #[default]
+    Jaccard,  // shared / all distinct, what pg_trgm reports
+    Dice,     // 2 · shared / (both sizes), kinder to strings of unequal length
+}
+
+impl Similarity {
+    pub fn score(self, shared: usize, first: usize, second: usize) -> f32 {
+        let (shared, total) = match self {
+            Similarity::Jaccard => (shared, first + second - shared),
+            Similarity::Dice => (2 * shared, first + second),
+        };
+        if total == 0 { 0.0 } else { shared as f32 / total as f32 }
+    }
+
+    // Fewest grams a string has to share with a `grams`-gram query to
+    // reach `threshold`, whatever its own size
+    pub fn min_shared(self, grams: usize, threshold: f32) -> usize {
+        let bound = match self {
+            Similarity::Jaccard => threshold * grams as f32,
+            Similarity::Dice => threshold * grams as f32 / (2.0 - threshold),
+        };
+        // The slack keeps rounding, e.g. 0.3 · 10 = 3.0000000000000004, from
+        // asking for one gram too many
+        ((bound - 1e-4).ceil() as usize).max(1)
+    }
+}
+
+// Size figures every index can report, e.g. for the build log
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub keys: usize,      // distinct folded keys (grams for the n-gram index)
+    pub postings: usize,  // ids stored across all keys
+    pub nodes: usize,     // tree nodes, 0 for flat indexes
+}
+
+pub type Matches<'a> = Box<dyn Iterator<Item = DocId> + 'a>;
+
+// Query side shared by every index, mutable or not
+pub trait Searchable: Send + Sync {
+    // Short name used in build logs and debug messages, e.g. "trie"
+    fn name(&self) -> &'static str;
+
+    fn normalization(&self) -> Normalization;
+
+    fn search<'a>(&'a self, query: &Query, strings: &'a StringTable) -> Option<Matches<'a>>;
+
+    // Whether `search` yields shorter keys before longer ones for `query`
+    fn shortest_first(&self, _query: &Query) -> bool {
+        false
+    }
+
+    // Frequency-ranked completions, for indexes that keep weights
+    fn top_k(&self, _prefix: &str, _k: usize) -> Option<Vec<(DocId, u32)>> {
+        None
+    }
+
+    // Stored keys that `input` starts with, shortest first, for indexes
+    // that can walk a key's path
+    fn prefixes_of<'a>(&'a self, _input: &str) -> Option<Vec<(String, &'a [DocId])>> {
+        None
+    }
+
+    // Exact occurrences of `term` anywhere in the stored text, for indexes
+    // over the full text rather than over keys
+    fn count(&self, _term: &str) -> Option<usize> {
+        None
+    }
+
+    // Each occurrence as (string, byte offset into its folded text)
+    fn positions(&self, _term: &str) -> Option<Vec<(DocId, usize)>> {
+        None
+    }
+
+    // Repeat statistics, for indexes that keep an LCP array
+    fn substrings(&self) -> Option<&dyn Substrings> {
+        None
+    }
+
+    // Stored strings whose gram sets are at least `threshold` alike to the
+    // key's, best first, for indexes that keep grams
+    fn similar(&self, _key: &str, _similarity: Similarity, _threshold: f32, _strings: &StringTable) -> Option<Vec<(DocId, f32)>> {
+        None
+    }
+
+    // Phrase and proximity lookups, for indexes that keep word positions
+    fn phrases(&self) -> Option<&dyn Phrases> {
+        None
+    }
+
+    // Stored occurrences
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn stats(&self) -> Stats;
+}
+
+// Analysis over the stored text rather than lookups. Substrings come back
+// folded like the text they were found in
+pub trait Substrings {
+    // Longest substring occurring at least twice, with its occurrence count
+    fn longest_repeated(&self) -> Option<(String, usize)>;
+
+    // The `n` most frequent substrings of `length` chars, most frequent first
+    fn most_frequent(&self, length: usize, n: usize) -> Vec<(String, usize)>;
+}
+
+// Word-level lookups inside stored lines. Phrases are split into words the
+// way the index split the lines; positions count words from 0
+pub trait Phrases {
+    // Lines holding the words of `phrase` back to back, with the position
+    // of every run's first word
+    fn phrase(&self, phrase: &str) -> Vec<(DocId, Vec<usize>)>;
+
+    // Lines where `first` and `second` occur at most `distance` words
+    // apart, in either order, with the start of every run involved
+    fn near(&self, first: &str, second: &str, distance: usize) -> Vec<(DocId, Vec<usize>)>;
+}
+
+// Indexes that are built in memory and serialized with bincode
+pub trait SearchStructure: Searchable + Encode + Decode<()> + Sized {
+    fn store(&mut self, key: String, strings: &mut StringTable);
+
+    fn remove(&mut self, key: String, strings: &StringTable) -> bool;
+
+    // Called once after the last `store` of a build, for indexes that sort
+    // or compact what was stored
+    fn finish(&mut self) {}
+
+    // `table` is the checksum of the string table the ids point into
+    fn encode_to_vec(&self, table: u64) -> Result<Vec<u8>, SearchError> {
+        encode_versioned(self, table)
+    }
+
+    // The structure and the checksum of the table it was built against
+    fn decode_from_slice(bytes: &[u8]) -> Result<(Self, u64), SearchError> {
+        decode_versioned(bytes)
+    }
+}
+
+// Bumped whenever a serialized layout changes, so stale files are rejected
+// with a clear message instead of decoding into garbage
+pub const FORMAT_VERSION: u32 = 7;
+
+// bincode behind FORMAT_VERSION and the checksum of the string table the
+// value's ids point into (see `StringTable::checksum`)
+pub fn encode_versioned<T: Encode>(value: &T, table: u64) -> Result<Vec<u8>, SearchError> {
+    Ok(bincode::encode_to_vec((FORMAT_VERSION, table, value), config::standard())?)
+}
+
+pub fn decode_versioned<T: Decode<()>>(bytes: &[u8]) -> Result<(T, u64), SearchError> {
+    let (found, read): (u32, usize) = bincode::decode_from_slice(bytes, config::standard())?;
+    if found != FORMAT_VERSION {
+        return Err(SearchError::VersionMismatch { expected: FORMAT_VERSION, found });
+    }
+    let ((table, decoded), _) = bincode::decode_from_slice(&bytes[read..], config::standard())?;
+    Ok((decoded, table))
+}
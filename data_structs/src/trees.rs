@@ -1,3 +1,8 @@
 pub mod trie;
+pub mod radix;
 pub mod suffix;
+pub mod suffix_array;
+pub mod fm_index;
 pub mod ngram;
+pub mod positional;
+pub mod fst;
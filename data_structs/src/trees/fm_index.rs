@@ -0,0 +1,512 @@
+use std::collections::HashMap;
+use bincode::{Decode, Encode};
+use regex::Regex;
+use crate::normalize::Normalization;
+use crate::prefilter;
+use crate::error::SearchError;
+use crate::strings::{DocId, StringTable};
+use crate::structure::{ContainsMode, Matches, Query, SearchStructure, Searchable, Stats};
+use super::suffix_array::{sort_suffixes, SEPARATOR};
+
+// Rows between two occurrence checkpoints; counting between them scans at
+// most this many BWT bytes
+const CHECKPOINT_INTERVAL: usize = 256;
+
+// Every how many text positions a suffix array entry is kept for `locate`
+pub const DEFAULT_SAMPLE_RATE: usize = 32;
+
+// FM-index over the same folded text as `SuffixArray`, but keeping only the
+// BWT, occurrence checkpoints and a sampled suffix array. Every distinct
+// string is stored once, like the suffix array. Counting needs the BWT and
+// the weights of rows whose string was stored more than once; locating walks
+// back to the nearest sample, at most `sample_rate` steps. `store` only
+// appends, the index is built by `finish`
+#[derive(Clone, Encode, Decode, Debug)]
+pub struct FmIndex {
+    text: Vec<u8>,                // folded entries while building, empty once finished
+    starts: Vec<u32>,             // where each entry begins in the text
+    ids: Vec<DocId>,              // string of each entry, parallel to `starts`
+    occurrences: Vec<u32>,        // times each entry was stored
+    entries: HashMap<DocId, u32>, // entry of each stored string
+    bwt: Vec<u8>,                 // byte before each sorted suffix
+    primary: u32,                 // row of the suffix starting the text, it has no byte before
+    alphabet: Vec<u8>,            // distinct bytes of the text, sorted
+    smaller: Vec<u32>,            // text bytes below each alphabet byte
+    checkpoints: Vec<u32>,        // per checkpoint, each byte's count in the BWT so far
+    sampled: Vec<u64>,            // bit per row, set when its position is sampled
+    sampled_before: Vec<u32>,     // sampled rows before each word of `sampled`
+    samples: Vec<u32>,            // text position of each sampled row, in row order
+    repeated: Vec<u64>,           // bit per row, set when its entry was stored more than once
+    repeated_before: Vec<u32>,    // repeated rows before each word of `repeated`, then the total
+    surplus: Vec<u64>,            // occurrences beyond the first summed over the repeated rows before each, then the total
+    sample_rate: usize,
+    normalization: Normalization, // applied to keys on store and to every query
+}
+
+impl Default for FmIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FmIndex {
+
+    pub fn new() -> Self {
+        Self::with_normalization(Normalization::default())
+    }
+
+    pub fn with_normalization(normalization: Normalization) -> Self {
+        Self::with_sample_rate(normalization, DEFAULT_SAMPLE_RATE)
+    }
+
+    // A lower rate locates faster at the cost of a larger sample table
+    pub fn with_sample_rate(normalization: Normalization, sample_rate: usize) -> Self {
+        Self {
+            text: Vec::new(),
+            starts: Vec::new(),
+            ids: Vec::new(),
+            occurrences: Vec::new(),
+            entries: HashMap::new(),
+            bwt: Vec::new(),
+            primary: 0,
+            alphabet: Vec::new(),
+            smaller: Vec::new(),
+            checkpoints: Vec::new(),
+            sampled: Vec::new(),
+            sampled_before: Vec::new(),
+            samples: Vec::new(),
+            repeated: Vec::new(),
+            repeated_before: Vec::new(),
+            surplus: Vec::new(),
+            sample_rate: sample_rate.max(1),
+            normalization,
+        }
+    }
+
+    pub fn normalization(&self) -> Normalization {
+        self.normalization
+    }
+
+    pub fn store(&mut self, key: String, strings: &mut StringTable) {
+        let id = strings.intern(&key);
+        if let Some(&entry) = self.entries.get(&id) {
+            self.occurrences[entry as usize] += 1;
+            return;
+        }
+
+        self.thaw();
+        self.entries.insert(id, self.ids.len() as u32);
+        self.starts.push(self.text.len() as u32);
+        self.ids.push(id);
+        self.occurrences.push(1);
+        self.text.extend_from_slice(self.normalization.apply(&key).as_bytes());
+        self.text.push(SEPARATOR);
+    }
+
+    // Forgets one occurrence of `key` and rebuilds the index, since even
+    // a repeat changes the row weights
+    pub fn remove(&mut self, key: String, strings: &StringTable) -> bool {
+        let removed = self.forget(&key, strings);
+        if removed {
+            self.finish();
+        }
+        removed
+    }
+
+    pub fn update(&mut self, old: String, new: String, strings: &mut StringTable) -> bool {
+        let removed = self.forget(&old, strings);
+        self.store(new, strings);
+        self.finish();
+        removed
+    }
+
+    // `remove` without the rebuild
+    fn forget(&mut self, key: &str, strings: &StringTable) -> bool {
+        let Some(&entry) = strings.id_of(key).and_then(|id| self.entries.get(&id)) else {
+            return false;
+        };
+        let entry = entry as usize;
+        self.occurrences[entry] -= 1;
+        if self.occurrences[entry] > 0 {
+            return true;
+        }
+        self.thaw();
+
+        let start = self.starts[entry] as usize;
+        let end = self.starts.get(entry + 1).map_or(self.text.len(), |&next| next as usize);
+        self.text.drain(start..end);
+        self.starts.remove(entry);
+        self.ids.remove(entry);
+        self.occurrences.remove(entry);
+        let removed = (end - start) as u32;
+        for start in self.starts[entry..].iter_mut() {
+            *start -= removed;
+        }
+        self.entries = self.ids.iter().enumerate().map(|(entry, &id)| (id, entry as u32)).collect();
+        true
+    }
+
+    // Builds the BWT, checkpoints and samples from the stored text, then
+    // drops the text; `thaw` recovers it from the BWT if more is stored
+    pub fn finish(&mut self) {
+        self.thaw();
+        let text = std::mem::take(&mut self.text);
+        let suffixes = sort_suffixes(&text);
+
+        self.bwt = Vec::with_capacity(text.len());
+        self.primary = 0;
+        for (row, &position) in suffixes.iter().enumerate() {
+            if position == 0 {
+                self.primary = row as u32;
+                self.bwt.push(SEPARATOR);
+            } else {
+                self.bwt.push(text[position as usize - 1]);
+            }
+        }
+
+        let mut counts = [0u32; 256];
+        for &byte in text.iter() {
+            counts[byte as usize] += 1;
+        }
+        self.alphabet = (0..=255u8).filter(|&byte| counts[byte as usize] > 0).collect();
+        let mut below = 0;
+        self.smaller = self.alphabet.iter().map(|&byte| {
+            let smaller = below;
+            below += counts[byte as usize];
+            smaller
+        }).collect();
+
+        // Checkpoint k holds each byte's count in bwt[..k * CHECKPOINT_INTERVAL]
+        let mut running = vec![0u32; self.alphabet.len()];
+        self.checkpoints = Vec::with_capacity((self.bwt.len() / CHECKPOINT_INTERVAL + 1) * self.alphabet.len());
+        for (row, &byte) in self.bwt.iter().enumerate() {
+            if row.is_multiple_of(CHECKPOINT_INTERVAL) {
+                self.checkpoints.extend_from_slice(&running);
+            }
+            if let Some(symbol) = self.symbol(byte) {
+                running[symbol] += 1;
+            }
+        }
+        if self.bwt.len().is_multiple_of(CHECKPOINT_INTERVAL) {
+            self.checkpoints.extend_from_slice(&running);
+        }
+
+        self.sampled = vec![0; suffixes.len().div_ceil(64)];
+        self.samples.clear();
+        for (row, &position) in suffixes.iter().enumerate() {
+            if (position as usize).is_multiple_of(self.sample_rate) {
+                self.sampled[row / 64] |= 1 << (row % 64);
+                self.samples.push(position);
+            }
+        }
+        let mut before = 0;
+        self.sampled_before = self.sampled.iter().map(|word| {
+            let sampled = before;
+            before += word.count_ones();
+            sampled
+        }).collect();
+
+        // A row weighs as much as its entry was stored; only rows weighing
+        // more than one are marked, most strings are stored once
+        self.repeated = vec![0; suffixes.len().div_ceil(64)];
+        self.surplus = vec![0];
+        let mut surplus = 0;
+        for (row, &position) in suffixes.iter().enumerate() {
+            let occurrences = self.occurrences[self.entry_at(position)] as u64;
+            if occurrences > 1 {
+                self.repeated[row / 64] |= 1 << (row % 64);
+                surplus += occurrences - 1;
+                self.surplus.push(surplus);
+            }
+        }
+        let mut before = 0;
+        self.repeated_before = self.repeated.iter().map(|word| {
+            let repeated = before;
+            before += word.count_ones();
+            repeated
+        }).collect();
+        self.repeated_before.push(before);
+    }
+
+    // Entries holding `key` anywhere, the default Contains mode, like the
+    // suffix array; see `search_iter` for strict infix matches
+    pub fn search(&self, key: String) -> Result<Vec<DocId>, SearchError> {
+        if key.is_empty() {
+            return Err(SearchError::InvalidQuery("an empty string is contained everywhere".to_string()));
+        }
+        let results: Vec<DocId> = self.search_iter(&key, ContainsMode::default()).collect();
+
+        if results.is_empty() {
+            Err(SearchError::NoMatch(key))
+        } else {
+            Ok(results)
+        }
+    }
+
+    // Locates every occurrence first, since in strict infix mode a string
+    // only qualifies when none of its occurrences touches either end
+    pub fn search_iter(&self, key: &str, mode: ContainsMode) -> impl Iterator<Item = DocId> + '_ {
+        let key = self.normalization.apply(key);
+        let length = key.len();
+        let mut order = Vec::new();
+        let mut inside: HashMap<DocId, bool> = HashMap::new();
+
+        for position in self.occurrences(key.as_bytes()) {
+            let entry = self.entry_at(position);
+            let offset = (position - self.starts[entry]) as usize;
+            let touches_end = offset == 0 || offset + length == self.entry_len(entry);
+            let qualifies = inside.entry(self.ids[entry]).or_insert_with(|| {
+                order.push(self.ids[entry]);
+                true
+            });
+            *qualifies &= mode.allows_ends() || !touches_end;
+        }
+        order.into_iter().filter(move |id| inside[id])
+    }
+
+    // Strings the regex matches, run only on those holding each of its
+    // required literals anywhere, in string table order. Works for lines
+    // too, unlike the word grams; `None` when the regex has no literal
+    pub fn regex_search_iter<'a>(&'a self, regex: &Regex, strings: &'a StringTable) -> Option<impl Iterator<Item = DocId> + 'a> {
+        let literals: Vec<String> = prefilter::required_literals(regex.as_str())
+            .into_iter()
+            .map(|literal| self.normalization.apply(&literal).into_owned())
+            .collect();
+        let candidates = prefilter::candidates(&literals, |literal| self.search_iter(literal, ContainsMode::Anywhere))?;
+
+        let regex = regex.clone(); // shares the compiled program
+        Some(candidates.into_iter().filter(move |&id| {
+            strings.resolve(id).is_some_and(|item| regex.is_match(item))
+        }))
+    }
+
+    // How often `key` occurs across everything stored, counting repeated
+    // strings once per time they were stored, without locating any row
+    pub fn count(&self, key: &str) -> usize {
+        let key = self.normalization.apply(key);
+        let (start, end) = self.rows(key.as_bytes());
+        if start == end {
+            return 0;
+        }
+        let surplus = self.surplus[self.repeated_rank(end)] - self.surplus[self.repeated_rank(start)];
+        end - start + surplus as usize
+    }
+
+    // Every place `key` occurs as (string, byte offset into its folded
+    // text), ordered by string then offset
+    pub fn locate(&self, key: &str) -> Vec<(DocId, usize)> {
+        let key = self.normalization.apply(key);
+        let mut positions: Vec<(DocId, usize)> = self
+            .occurrences(key.as_bytes())
+            .map(|position| {
+                let entry = self.entry_at(position);
+                (self.ids[entry], (position - self.starts[entry]) as usize)
+            })
+            .collect();
+        positions.sort_unstable();
+        positions
+    }
+
+    fn occurrences(&self, pattern: &[u8]) -> impl Iterator<Item = u32> + '_ {
+        let (start, end) = self.rows(pattern);
+        (start..end).map(|row| self.position_of(row))
+    }
+
+    // Backward search: the rows whose suffixes start with `pattern`
+    fn rows(&self, pattern: &[u8]) -> (usize, usize) {
+        if pattern.is_empty() || pattern.contains(&SEPARATOR) {
+            return (0, 0);
+        }
+        let (mut start, mut end) = (0, self.bwt.len());
+        for &byte in pattern.iter().rev() {
+            let Some(symbol) = self.symbol(byte) else {
+                return (0, 0);
+            };
+            start = self.smaller[symbol] as usize + self.occ(symbol, byte, start);
+            end = self.smaller[symbol] as usize + self.occ(symbol, byte, end);
+            if start >= end {
+                return (0, 0);
+            }
+        }
+        (start, end)
+    }
+
+    // Walks LF back to the nearest sampled row
+    fn position_of(&self, mut row: usize) -> u32 {
+        let mut steps = 0;
+        while self.sampled[row / 64] & (1 << (row % 64)) == 0 {
+            row = self.lf(row);
+            steps += 1;
+        }
+        let below = (self.sampled[row / 64] & ((1 << (row % 64)) - 1)).count_ones();
+        self.samples[(self.sampled_before[row / 64] + below) as usize] + steps
+    }
+
+    // Row of the suffix one byte before the suffix at `row`. The lone final
+    // separator sorts before every other suffix starting with one, and no
+    // row maps onto it
+    fn lf(&self, row: usize) -> usize {
+        let byte = self.bwt[row];
+        let symbol = self.symbol(byte).expect("BWT bytes are in the alphabet");
+        let last = (byte == SEPARATOR) as usize;
+        self.smaller[symbol] as usize + last + self.occ(symbol, byte, row)
+    }
+
+    // Count of `byte` in bwt[..row], leaving out the primary row
+    fn occ(&self, symbol: usize, byte: u8, row: usize) -> usize {
+        let checkpoint = row / CHECKPOINT_INTERVAL;
+        let from = checkpoint * CHECKPOINT_INTERVAL;
+        let counted = self.checkpoints[checkpoint * self.alphabet.len() + symbol] as usize;
+        let scanned = self.bwt[from..row].iter().filter(|&&other| other == byte).count();
+        let primary = (byte == SEPARATOR && (self.primary as usize) < row) as usize;
+        counted + scanned - primary
+    }
+
+    // Repeated rows before `row`
+    fn repeated_rank(&self, row: usize) -> usize {
+        let below = self.repeated.get(row / 64).map_or(0, |word| (word & ((1 << (row % 64)) - 1)).count_ones());
+        (self.repeated_before[row / 64] + below) as usize
+    }
+
+    fn symbol(&self, byte: u8) -> Option<usize> {
+        self.alphabet.binary_search(&byte).ok()
+    }
+
+    fn entry_at(&self, position: u32) -> usize {
+        self.starts.partition_point(|&start| start <= position) - 1
+    }
+
+    // Folded length of an entry, without its separator
+    fn entry_len(&self, entry: usize) -> usize {
+        let end = self.starts.get(entry + 1).map_or(self.bwt.len(), |&next| next as usize);
+        end - self.starts[entry] as usize - 1
+    }
+
+    // Recovers the text from the BWT so more can be stored after `finish`
+    fn thaw(&mut self) {
+        if !self.text.is_empty() || self.bwt.is_empty() {
+            return;
+        }
+        // Row 0 is the final separator; each LF step moves one byte back
+        let mut text = vec![SEPARATOR; self.bwt.len()];
+        let mut row = 0;
+        for position in (0..self.bwt.len() - 1).rev() {
+            text[position] = self.bwt[row];
+            row = self.lf(row);
+        }
+        self.text = text;
+        self.bwt.clear();
+    }
+}
+
+impl Searchable for FmIndex {
+    fn name(&self) -> &'static str {
+        "fm-index"
+    }
+
+    fn normalization(&self) -> Normalization {
+        self.normalization
+    }
+
+    fn search<'a>(&'a self, query: &Query, strings: &'a StringTable) -> Option<Matches<'a>> {
+        match *query {
+            Query::Contains(key, mode) => Some(Box::new(self.search_iter(key, mode))),
+            Query::Regex(regex) => self
+                .regex_search_iter(regex, strings)
+                .map(|matches| Box::new(matches) as Matches<'a>),
+            _ => None,
+        }
+    }
+
+    fn count(&self, term: &str) -> Option<usize> {
+        Some(FmIndex::count(self, term))
+    }
+
+    fn positions(&self, term: &str) -> Option<Vec<(DocId, usize)>> {
+        Some(self.locate(term))
+    }
+
+    fn len(&self) -> usize {
+        self.occurrences.iter().map(|&count| count as usize).sum()
+    }
+
+    fn stats(&self) -> Stats {
+        Stats {
+            keys: self.ids.len(),
+            postings: self.samples.len(),
+            nodes: 0,
+        }
+    }
+}
+
+impl SearchStructure for FmIndex {
+    fn store(&mut self, key: String, strings: &mut StringTable) {
+        FmIndex::store(self, key, strings)
+    }
+
+    fn remove(&mut self, key: String, strings: &StringTable) -> bool {
+        FmIndex::remove(self, key, strings)
+    }
+
+    fn finish(&mut self) {
+        FmIndex::finish(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{corpus, patterns};
+    use crate::trees::suffix_array::SuffixArray;
+
+    // Both indexes over the same strings, sharing one string table
+    fn build(strings: &[String], sample_rate: usize) -> (FmIndex, SuffixArray, StringTable) {
+        let mut table = StringTable::new();
+        let mut fm_index = FmIndex::with_sample_rate(Normalization::default(), sample_rate);
+        let mut suffix_array = SuffixArray::new();
+        for string in strings.iter() {
+            fm_index.store(string.clone(), &mut table);
+            suffix_array.store(string.clone(), &mut table);
+        }
+        fm_index.finish();
+        suffix_array.finish();
+        (fm_index, suffix_array, table)
+    }
+
+    #[test]
+    fn count_and_locate_match_the_suffix_array() {
+        let strings = corpus(11);
+        // Sampling every position, some and few, so locate walks back by
+        // none, a few and many steps
+        for sample_rate in [1, 3, DEFAULT_SAMPLE_RATE] {
+            let (fm_index, suffix_array, _) = build(&strings, sample_rate);
+            for pattern in patterns() {
+                assert_eq!(fm_index.count(&pattern), suffix_array.count(&pattern), "count of {:?} at rate {}", pattern, sample_rate);
+                assert_eq!(fm_index.locate(&pattern), suffix_array.positions(&pattern), "locate of {:?} at rate {}", pattern, sample_rate);
+            }
+        }
+    }
+
+    #[test]
+    fn removing_after_a_build_recovers_the_text() {
+        let (mut fm_index, mut suffix_array, table) = build(&corpus(11), 3);
+        // One of two repeats, then the last occurrence of a string
+        for removed in ["banana", "bandana"] {
+            assert!(fm_index.remove(removed.to_string(), &table));
+            assert!(suffix_array.remove(removed.to_string(), &table));
+        }
+
+        for pattern in ["ban", "ana", "nd", "a"] {
+            assert_eq!(fm_index.count(pattern), suffix_array.count(pattern), "count of {:?}", pattern);
+            assert_eq!(fm_index.locate(pattern), suffix_array.positions(pattern), "locate of {:?}", pattern);
+        }
+    }
+
+    #[test]
+    fn patterns_never_match_across_entries() {
+        let (fm_index, _, _) = build(&["ab".to_string(), "cd".to_string()], 1);
+        assert_eq!(fm_index.count("b\0c"), 0);
+        assert_eq!(fm_index.count(""), 0);
+        assert!(fm_index.locate("b\0c").is_empty());
+    }
+}
@@ -0,0 +1,279 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::Arc;
+use fst::{IntoStreamer, Map, MapBuilder, Streamer};
+use memmap2::Mmap;
+use crate::normalize::Normalization;
+use crate::error::SearchError;
+use crate::strings::{DocId, StringTable};
+use crate::structure::{decode_versioned, encode_versioned, Matches, Query, Searchable, Stats};
+
+// File names inside a scope directory of `serialized_outputs`
+pub const FORWARD_FILE: &str = "fst-forward.fst";
+pub const REVERSE_FILE: &str = "fst-reverse.fst";
+pub const POSTINGS_FILE: &str = "fst-postings.bin";
+pub const META_FILE: &str = "fst-meta.bin";
+
+// Collects keys in memory and writes them out as an immutable FstIndex.
+// Keys are folded once here; the FSTs map every distinct folded key to the
+// offset of its ids in the postings file
+#[derive(Debug, Default)]
+pub struct FstBuilder {
+    keys: BTreeMap<String, Vec<DocId>>,
+    normalization: Normalization,
+}
+
+impl FstBuilder {
+
+    pub fn new() -> Self {
+        Self::with_normalization(Normalization::default())
+    }
+
+    pub fn with_normalization(normalization: Normalization) -> Self {
+        Self {
+            keys: BTreeMap::new(),
+            normalization,
+        }
+    }
+
+    pub fn store(&mut self, key: String, strings: &mut StringTable) {
+        let id = strings.intern(&key);
+        let path = self.normalization.apply(&key).into_owned();
+        self.keys.entry(path).or_default().push(id);
+    }
+
+    // `table` is the checksum of the string table the ids point into
+    pub fn write(&self, dir: &Path, table: u64) -> Result<(), SearchError> {
+        // Postings are little endian u32s: the id count, then the ids
+        let mut postings: Vec<u8> = Vec::new();
+        let mut reversed = Vec::with_capacity(self.keys.len());
+
+        let path = dir.join(FORWARD_FILE);
+        let file = File::create(&path).map_err(|e| SearchError::io(&path, e))?;
+        let mut forward = MapBuilder::new(BufWriter::new(file))?;
+        for (key, ids) in self.keys.iter() {
+            let offset = (postings.len() / 4) as u64;
+            postings.extend_from_slice(&(ids.len() as u32).to_le_bytes());
+            for id in ids.iter() {
+                postings.extend_from_slice(&id.to_le_bytes());
+            }
+            forward.insert(key, offset)?;
+            reversed.push((key.chars().rev().collect::<String>(), offset));
+        }
+        forward.finish()?;
+
+        // FSTs need their keys in order, and reversing breaks the order
+        reversed.sort_unstable();
+        let path = dir.join(REVERSE_FILE);
+        let file = File::create(&path).map_err(|e| SearchError::io(&path, e))?;
+        let mut reverse = MapBuilder::new(BufWriter::new(file))?;
+        for (key, offset) in reversed.iter() {
+            reverse.insert(key, *offset)?;
+        }
+        reverse.finish()?;
+
+        let path = dir.join(POSTINGS_FILE);
+        fs::write(&path, postings).map_err(|e| SearchError::io(&path, e))?;
+        let path = dir.join(META_FILE);
+        fs::write(&path, encode_versioned(&self.normalization, table)?).map_err(|e| SearchError::io(&path, e))?;
+        Ok(())
+    }
+}
+
+// Bytes of a memory-mapped file, shared between clones
+#[derive(Clone)]
+struct Mapped(Arc<Mmap>);
+
+impl Mapped {
+
+    fn open(path: &Path) -> Result<Self, SearchError> {
+        let file = File::open(path).map_err(|e| SearchError::io(path, e))?;
+        // The serialized outputs are written once by `compile` and only read afterwards
+        let map = unsafe { Mmap::map(&file) }.map_err(|e| SearchError::io(path, e))?;
+        Ok(Self(Arc::new(map)))
+    }
+}
+
+impl AsRef<[u8]> for Mapped {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+// Read-only prefix, suffix and range index over memory-mapped FSTs. Opening
+// it only maps the files, pages are loaded as queries touch them
+#[derive(Clone)]
+pub struct FstIndex {
+    forward: Map<Mapped>,
+    reverse: Map<Mapped>,  // keys reversed char by char, for suffix queries
+    postings: Mapped,
+    normalization: Normalization,
+    table: u64,
+}
+
+impl FstIndex {
+
+    pub fn open(dir: &Path) -> Result<Self, SearchError> {
+        // The meta file carries the format version, check it before anything else
+        let path = dir.join(META_FILE);
+        let meta = fs::read(&path).map_err(|e| SearchError::io(&path, e))?;
+        let (normalization, table) = decode_versioned(&meta)?;
+
+        let forward = Map::new(Mapped::open(&dir.join(FORWARD_FILE))?)?;
+        let reverse = Map::new(Mapped::open(&dir.join(REVERSE_FILE))?)?;
+        let postings = Mapped::open(&dir.join(POSTINGS_FILE))?;
+
+        Ok(Self {
+            forward,
+            reverse,
+            postings,
+            normalization,
+            table,
+        })
+    }
+
+    pub fn normalization(&self) -> Normalization {
+        self.normalization
+    }
+
+    // Checksum of the string table the index was built against
+    pub fn table(&self) -> u64 {
+        self.table
+    }
+
+    pub fn search(&self, prefix: String) -> Result<Vec<DocId>, SearchError> {
+        let results: Vec<DocId> = self.search_iter(&prefix).collect();
+        if results.is_empty() {
+            Err(SearchError::NoMatch(prefix))
+        } else {
+            Ok(results)
+        }
+    }
+
+    // Keys starting with `prefix` in lexicographic order, the prefix itself
+    // is not a match (same as the Trie)
+    pub fn search_iter(&self, prefix: &str) -> impl Iterator<Item = DocId> + '_ {
+        let prefix = self.normalization.apply(prefix).into_owned().into_bytes();
+        KeyStream::new(&self.forward, prefix, self.postings.as_ref())
+    }
+
+    pub fn suffix_search(&self, suffix: String) -> Result<Vec<DocId>, SearchError> {
+        let results: Vec<DocId> = self.suffix_search_iter(&suffix).collect();
+        if results.is_empty() {
+            Err(SearchError::NoMatch(suffix))
+        } else {
+            Ok(results)
+        }
+    }
+
+    // Keys ending with `suffix`, the suffix itself is not a match (same as the SuffixTree)
+    pub fn suffix_search_iter(&self, suffix: &str) -> impl Iterator<Item = DocId> + '_ {
+        let reversed: String = self.normalization.apply(suffix).chars().rev().collect();
+        KeyStream::new(&self.reverse, reversed.into_bytes(), self.postings.as_ref())
+    }
+
+    // Keys between the two bounds in lexicographic order, bounds are folded like keys
+    pub fn range_iter(&self, start: Bound<&str>, end: Bound<&str>) -> impl Iterator<Item = DocId> + '_ {
+        let mut range = self.forward.range();
+        range = match start {
+            Bound::Included(start) => range.ge(self.normalization.apply(start).as_bytes()),
+            Bound::Excluded(start) => range.gt(self.normalization.apply(start).as_bytes()),
+            Bound::Unbounded => range,
+        };
+        range = match end {
+            Bound::Included(end) => range.le(self.normalization.apply(end).as_bytes()),
+            Bound::Excluded(end) => range.lt(self.normalization.apply(end).as_bytes()),
+            Bound::Unbounded => range,
+        };
+        KeyStream {
+            stream: range.into_stream(),
+            prefix: None,
+            postings: self.postings.as_ref(),
+            ids: [].chunks_exact(4),
+        }
+    }
+}
+
+// Streams the ids of consecutive FST keys; with a prefix the stream starts
+// at it and stops at the first key that does not share it
+struct KeyStream<'a> {
+    stream: fst::map::Stream<'a>,
+    prefix: Option<Vec<u8>>,
+    postings: &'a [u8],
+    ids: std::slice::ChunksExact<'a, u8>,
+}
+
+impl<'a> KeyStream<'a> {
+    fn new(map: &'a Map<Mapped>, prefix: Vec<u8>, postings: &'a [u8]) -> Self {
+        Self {
+            stream: map.range().ge(&prefix).into_stream(),
+            prefix: Some(prefix),
+            postings,
+            ids: [].chunks_exact(4),
+        }
+    }
+}
+
+impl Iterator for KeyStream<'_> {
+    type Item = DocId;
+
+    fn next(&mut self) -> Option<DocId> {
+        loop {
+            if let Some(id) = self.ids.next() {
+                return Some(u32::from_le_bytes([id[0], id[1], id[2], id[3]]));
+            }
+
+            let (key, offset) = self.stream.next()?;
+            if let Some(prefix) = &self.prefix {
+                if !key.starts_with(prefix) {
+                    return None;
+                }
+                if key == prefix.as_slice() {
+                    continue;
+                }
+            }
+
+            // A truncated postings file ends the stream rather than panicking
+            let start = offset as usize * 4;
+            let count = self.postings.get(start..start + 4)?;
+            let count = u32::from_le_bytes([count[0], count[1], count[2], count[3]]) as usize;
+            self.ids = self.postings.get(start + 4..start + 4 + count * 4)?.chunks_exact(4);
+        }
+    }
+}
+
+impl Searchable for FstIndex {
+    fn name(&self) -> &'static str {
+        "fst"
+    }
+
+    fn normalization(&self) -> Normalization {
+        self.normalization
+    }
+
+    fn search<'a>(&'a self, query: &Query, _strings: &'a StringTable) -> Option<Matches<'a>> {
+        match *query {
+            Query::Prefix(prefix) => Some(Box::new(self.search_iter(prefix))),
+            Query::Suffix(suffix) => Some(Box::new(self.suffix_search_iter(suffix))),
+            Query::Range { start, end } => Some(Box::new(self.range_iter(start, end))),
+            _ => None,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.stats().postings
+    }
+
+    fn stats(&self) -> Stats {
+        // Every key has one count word in front of its ids
+        let keys = self.forward.len();
+        Stats {
+            keys,
+            postings: self.postings.as_ref().len() / 4 - keys,
+            nodes: 0,
+        }
+    }
+}
@@ -1,12 +1,21 @@
-use std::{cmp::Ordering,collections::{HashMap, HashSet}};
+use std::collections::{HashMap, HashSet};
 use unicode_segmentation::UnicodeSegmentation;
 use bincode::{Encode,Decode};
+use crate::normalize::Normalization;
+use crate::error::SearchError;
+use crate::strings::{DocId, StringTable};
+use crate::structure::{ContainsMode, Matches, Query, SearchStructure, Searchable, Similarity, Stats};
+use crate::wildcard::Pattern;
+use crate::prefilter::required_literals;
+use crate::postings::PostingList;
+use regex::Regex;
 
 #[derive(Encode, Decode, Debug, Clone)]
 pub struct NGramIndex {
-    grams: Option<HashMap<Vec<String>, Vec<usize>>>,
-    words: Option<Vec<String>>,
-    gram_size: usize,
+    grams: Option<HashMap<Vec<String>, PostingList>>,  // compressed, sorted, each id once
+    words: Option<Vec<DocId>>,  // one entry per stored occurrence
+    gram_sizes: Vec<usize>,  // sorted, every size is indexed for each key
+    normalization: Normalization,  // applied to keys on store and to every query
     pub search_type: SearchScopeNgram,
 }
 
@@ -16,6 +25,9 @@ pub enum SearchScopeNgram {
     Lines,
 }
 
+// Bigrams only, what the index was first built with
+pub const DEFAULT_GRAM_SIZES: [usize; 1] = [2];
+
 impl Default for NGramIndex {
     fn default() -> Self {
         Self::new()
@@ -25,125 +37,335 @@ impl Default for NGramIndex {
 impl NGramIndex {
 
     pub fn new() -> Self {
-        Self { 
-            grams: None, 
-            words: None, 
-            gram_size: 2, 
+        Self::with_normalization(Normalization::default())
+    }
+
+    pub fn with_normalization(normalization: Normalization) -> Self {
+        Self::with_gram_sizes(normalization, &DEFAULT_GRAM_SIZES)
+    }
+
+    // Indexes grams of every size in `sizes`, e.g. 1..=3; zero sizes are
+    // ignored and no usable size falls back to DEFAULT_GRAM_SIZES
+    pub fn with_gram_sizes(normalization: Normalization, sizes: &[usize]) -> Self {
+        let mut gram_sizes: Vec<usize> = sizes.iter().copied().filter(|&size| size > 0).collect();
+        gram_sizes.sort_unstable();
+        gram_sizes.dedup();
+        if gram_sizes.is_empty() {
+            gram_sizes = DEFAULT_GRAM_SIZES.to_vec();
+        }
+        Self {
+            grams: None,
+            words: None,
+            gram_sizes,
+            normalization,
             search_type: SearchScopeNgram::Words
         }
     }
 
-    pub fn store(&mut self, key: String) {
-        let ngram = self;
-        let line_segment = key.unicode_words().map(|x| x.to_string()).collect::<Vec<String>>();
+    pub fn normalization(&self) -> Normalization {
+        self.normalization
+    }
+
+    pub fn gram_sizes(&self) -> &[usize] {
+        &self.gram_sizes
+    }
+
+    // What grams are made of: graphemes (visible characters) for words,
+    // words for lines
+    fn units<'k>(&self, key: &'k str) -> Vec<&'k str> {
+        match self.search_type {
+            SearchScopeNgram::Words => key.graphemes(true).collect(),
+            SearchScopeNgram::Lines => key.unicode_words().collect(),
+        }
+    }
+
+    fn gram(&self, units: &[&str]) -> Vec<String> {
+        match self.search_type {
+            SearchScopeNgram::Words => vec![units.concat()],
+            SearchScopeNgram::Lines => units.iter().map(|unit| unit.to_string()).collect(),
+        }
+    }
+
+    fn grams_of(&self, key: &str) -> Vec<Vec<String>> {
+        let key = self.normalization.apply(key);
+        let units = self.units(&key);
+        // A key shorter than every gram size is kept whole, so contains
+        // queries can still reach it through the grams holding the term
+        if !units.is_empty() && units.len() < self.gram_sizes[0] {
+            return vec![self.gram(&units)];
+        }
+        self.gram_sizes
+            .iter()
+            .flat_map(|&size| units.windows(size))
+            .map(|window| self.gram(window))
+            .collect()
+    }
+
+    pub fn store(&mut self, key: String, strings: &mut StringTable) {
+        let id = strings.intern(&key);
+        let keys = self.grams_of(&key);
+        let grams = self.grams.get_or_insert_with(HashMap::new);
+
+        for gram in keys {
+            // Ids mostly arrive in increasing order, so this is an append
+            grams.entry(gram).or_default().insert(id);
+        }
+        self.words.get_or_insert_with(Vec::new).push(id);
+    }
 
-        let key_length = match ngram.search_type {
-            SearchScopeNgram::Words => {
-                // For words, we need to count graphemes (visible characters)
-                key.graphemes(true).count()
-            },
-            SearchScopeNgram::Lines => line_segment.len(),
+    // Forgets one occurrence of `key`; its id leaves the posting lists once
+    // no other occurrence of the same text is left
+    pub fn remove(&mut self, key: String, strings: &StringTable) -> bool {
+        let Some(id) = strings.id_of(&key) else {
+            return false;
         };
+        let Some(words) = self.words.as_mut() else {
+            return false;
+        };
+        let Some(position) = words.iter().rposition(|&word| word == id) else {
+            return false;
+        };
+        words.swap_remove(position);
+        if words.contains(&id) {
+            return true;
+        }
 
-        if ngram.words.is_none() {
-            let mut new_hash = HashMap::new();
-
-            for index in (ngram.gram_size - 1)..key_length {
-                match ngram.search_type {
-                    SearchScopeNgram::Words => {
-                        // Get graphemes properly
-                        let graphemes: Vec<&str> = key.graphemes(true).collect();
-                        if index + ngram.gram_size > graphemes.len() {
-                            continue;
-                        }
-                        let word_segment = graphemes[index..index + ngram.gram_size].join("");
-                        let keys: Vec<String> = vec![word_segment];
-                        new_hash.insert(keys.clone(), vec![0]);
-                    },
-                    SearchScopeNgram::Lines => {
-                        if index + ngram.gram_size > line_segment.len() {
-                            continue;
-                        }
-                        let keys: Vec<String> = line_segment[index..index + ngram.gram_size].to_vec();
-                        new_hash.insert(keys, vec![0]);
-                    },
-                };
+        let keys = self.grams_of(&key);
+        if let Some(grams) = self.grams.as_mut() {
+            for gram in keys {
+                if let Some(ids) = grams.get_mut(&gram) {
+                    ids.remove(id);
+                    if ids.is_empty() {
+                        grams.remove(&gram);
+                    }
+                }
             }
-            ngram.grams = Some(new_hash);
-            ngram.words = Some(vec![key]);
+        }
+        true
+    }
+
+    pub fn update(&mut self, old: String, new: String, strings: &mut StringTable) -> bool {
+        let removed = self.remove(old, strings);
+        self.store(new, strings);
+        removed
+    }
+
+    pub fn search(&self, key: String, strings: &StringTable) -> Result<Vec<DocId>, SearchError> {
+        if key.trim().is_empty() {
+            return Err(SearchError::InvalidQuery("an empty string is contained everywhere".to_string()));
+        }
+        let results: Vec<DocId> = self.search_iter(&key, ContainsMode::default(), strings).collect();
+
+        if results.is_empty() {
+            Err(SearchError::NoMatch(key))
         } else {
-            for index in (ngram.gram_size - 1)..key_length {
-                match ngram.search_type {
-                    SearchScopeNgram::Words => {
-                        let graphemes: Vec<&str> = key.graphemes(true).collect();
-                        if index + ngram.gram_size > graphemes.len() {
-                            continue;
-                        }
-                        let word_segment = graphemes[index..index + ngram.gram_size].join("");
-                        let keys: Vec<String> = vec![word_segment];
-                        ngram.grams.as_mut().unwrap()
-                            .entry(keys)
-                            .and_modify(|v| v.push(ngram.words.as_ref().unwrap().len()))
-                            .or_insert(vec![ngram.words.as_ref().unwrap().len()]);
-                    },
-                    SearchScopeNgram::Lines => {
-                        if index + ngram.gram_size > line_segment.len() {
-                            continue;
-                        }
-                        let keys: Vec<String> = line_segment[index..index + ngram.gram_size].to_vec();
-                        ngram.grams.as_mut().unwrap()
-                            .entry(keys)
-                            .and_modify(|v| v.push(ngram.words.as_ref().unwrap().len()))
-                            .or_insert(vec![ngram.words.as_ref().unwrap().len()]);
-                    },
-                }
+            Ok(results)
+        }
+    }
+
+    // Lazy version of `search`; each candidate is checked against the
+    // folded text and yielded once
+    pub fn search_iter<'a>(&'a self, key: &str, mode: ContainsMode, strings: &'a StringTable) -> impl Iterator<Item = DocId> + 'a {
+        let key = self.normalization.apply(key).into_owned();
+        let candidates = self.candidates(&key);
+        let mut seen = HashSet::new();
+
+        candidates.filter(move |&id| {
+            seen.insert(id)
+                && strings.resolve(id).is_some_and(|text| {
+                    let text = self.normalization.apply(text);
+                    let inside = mode.allows_ends() || (!text.starts_with(&key) && !text.ends_with(&key));
+                    inside && text.contains(&key)
+                })
+        })
+    }
+
+    // Ids that may contain the folded `key`. A key at least as long as some
+    // gram size is split into grams of the largest such size, each looked
+    // up directly, and their posting lists are intersected. A shorter key
+    // can only sit inside longer grams, so those are scanned for it
+    fn candidates<'a>(&'a self, key: &str) -> Box<dyn Iterator<Item = DocId> + 'a> {
+        let units = self.units(key);
+        let Some(grams) = self.grams.as_ref().filter(|_| !units.is_empty()) else {
+            return Box::new(std::iter::empty());
+        };
+
+        let Some(&size) = self.gram_sizes.iter().rev().find(|&&size| size <= units.len()) else {
+            let key: Vec<String> = units.iter().map(|unit| unit.to_string()).collect();
+            let joined = key.concat();
+            let inside = move |gram: &Vec<String>| match self.search_type {
+                SearchScopeNgram::Words => gram[0].contains(&joined),
+                SearchScopeNgram::Lines => gram.windows(key.len()).any(|window| window == key.as_slice()),
+            };
+            return Box::new(
+                grams
+                    .iter()
+                    .filter(move |(gram, _)| inside(gram))
+                    .flat_map(|(_, ids)| ids.iter()),
+            );
+        };
+
+        // A gram nobody has rules every key out
+        let postings: Option<Vec<&PostingList>> = units.windows(size).map(|window| grams.get(&self.gram(window))).collect();
+        match postings {
+            Some(mut postings) => Box::new(intersect(&mut postings).into_iter()),
+            None => Box::new(std::iter::empty()),
+        }
+    }
+
+    // Keys matching a `?`/`*` pattern, checked against the candidates of the
+    // pattern's literals. `None` for line grams or when no literal has an
+    // indexed gram
+    pub fn wildcard_search_iter<'a>(&'a self, pattern: &str, strings: &'a StringTable) -> Option<impl Iterator<Item = DocId> + 'a> {
+        let pattern = Pattern::parse(&self.normalization.apply(pattern));
+        let candidates = self.literal_candidates(&pattern.literals())?;
+
+        Some(candidates.into_iter().filter(move |&id| {
+            strings
+                    .resolve(id)
+                    .is_some_and(|word| pattern.matches(&self.normalization.apply(word)))
+        }))
+    }
+
+    // Keys the regex matches, run only on the candidates of its required
+    // literals (the trigram-index technique). The regex sees the original
+    // text; `None` when it has no literal with an indexed gram
+    pub fn regex_search_iter<'a>(&'a self, regex: &Regex, strings: &'a StringTable) -> Option<impl Iterator<Item = DocId> + 'a> {
+        let literals: Vec<String> = required_literals(regex.as_str())
+            .into_iter()
+            .map(|literal| self.normalization.apply(&literal).into_owned())
+            .collect();
+        let candidates = self.literal_candidates(&literals)?;
+
+        let regex = regex.clone(); // shares the compiled program
+        Some(candidates.into_iter().filter(move |&id| {
+            strings.resolve(id).is_some_and(|word| regex.is_match(word))
+        }))
+    }
+
+    // Stored strings sharing at least `threshold` of their distinct grams
+    // with `key`, best first (pg_trgm's `%`). Candidates are counted off
+    // the posting lists of the key's grams, and only those sharing enough
+    // grams to possibly reach the threshold are read back and scored
+    pub fn similar(&self, key: &str, similarity: Similarity, threshold: f32, strings: &StringTable) -> Vec<(DocId, f32)> {
+        let grams: HashSet<Vec<String>> = self.grams_of(key).into_iter().collect();
+        let Some(index) = self.grams.as_ref().filter(|_| !grams.is_empty()) else {
+            return Vec::new();
+        };
+
+        let mut shared: HashMap<DocId, usize> = HashMap::new();
+        for ids in grams.iter().filter_map(|gram| index.get(gram)) {
+            for id in ids.iter() {
+                *shared.entry(id).or_default() += 1;
             }
-            ngram.words.as_mut().unwrap().push(key);
         }
+
+        let min_shared = similarity.min_shared(grams.len(), threshold);
+        let mut results: Vec<(DocId, f32)> = shared
+            .into_iter()
+            .filter(|&(_, count)| count >= min_shared)
+            .filter_map(|(id, count)| {
+                let own: HashSet<Vec<String>> = self.grams_of(strings.resolve(id)?).into_iter().collect();
+                let score = similarity.score(count, grams.len(), own.len());
+                (score >= threshold).then_some((id, score))
+            })
+            .collect();
+        results.sort_unstable_by(|(id, score), (other_id, other)| other.total_cmp(score).then(id.cmp(other_id)));
+        results
     }
 
-    pub fn search(&self, key: String) -> Result<Vec<String>, &str> {
-        let ngram = self;
-        let mut results = HashSet::new(); 
+    // Ids holding every gram of the folded `literals`, each literal split
+    // into grams of the largest size it fits. Only word grams are looked up
+    fn literal_candidates(&self, literals: &[String]) -> Option<Vec<DocId>> {
+        let SearchScopeNgram::Words = self.search_type else {
+            return None;
+        };
+        let grams = self.grams.as_ref();
+        let mut postings: Vec<&PostingList> = Vec::new();
 
-        for (key_in_gram, values) in ngram.grams.as_ref().unwrap().iter() {
-            let condition = match ngram.search_type {
-                SearchScopeNgram::Words => {
-                    match key.len().cmp(&ngram.gram_size) {
-                        Ordering::Less => key_in_gram.iter().any(|x| x.contains(&key)),
-                        Ordering::Equal => key_in_gram.iter().any(|x| x == &key),
-                        Ordering::Greater => false
-                    }
-                },
-                SearchScopeNgram::Lines => {
-                    let key_parts: Vec<&str> = key.split_whitespace().collect();
-                    match key_parts.len().cmp(&ngram.gram_size) {
-                        Ordering::Less => {
-                            key_in_gram.iter().any(|word| word == &key)
-                        },
-                        Ordering::Equal => {
-                            let joined = key_in_gram.join(" ");
-                            joined == key
-                        },
-                        Ordering::Greater => false
-                    }
-                },
+        for literal in literals.iter() {
+            let units = self.units(literal);
+            let Some(&size) = self.gram_sizes.iter().rev().find(|&&size| size <= units.len()) else {
+                continue;
             };
-            if condition {
-                for value in values.iter() {
-                    if let Some(word) = ngram.words.as_ref().unwrap().get(*value) {
-                        if !word.starts_with(&key) && !word.ends_with(&key) && word.contains(&key) {
-                            results.insert(word.clone()); // HashSet automatically handles duplicates
-                        }
-                    }
+            for window in units.windows(size) {
+                match grams.and_then(|grams| grams.get(&self.gram(window))) {
+                    Some(ids) => postings.push(ids),
+                    None => return Some(Vec::new()),
                 }
             }
         }
+        (!postings.is_empty()).then(|| intersect(&mut postings))
+    }
+}
 
-        if results.is_empty() {
-            Err("couldn't find a match mate")
-        } else {
-            Ok(results.into_iter().collect()) 
+// Ids on every list. Lists are taken smallest first and each id left is
+// sought in the next list from where the previous one was found, its skip
+// pointers jumping over blocks, so a short list against a long one only
+// decodes the blocks it lands in
+fn intersect(lists: &mut [&PostingList]) -> Vec<DocId> {
+    lists.sort_unstable_by_key(|ids| ids.len());
+    let Some((smallest, rest)) = lists.split_first() else {
+        return Vec::new();
+    };
+
+    let mut result: Vec<DocId> = smallest.iter().collect();
+    for list in rest.iter() {
+        let mut cursor = list.iter();
+        result.retain(|&id| cursor.seek(id) == Some(id));
+        if result.is_empty() {
+            break;
+        }
+    }
+    result
+}
+
+impl Searchable for NGramIndex {
+    fn name(&self) -> &'static str {
+        "ngram"
+    }
+
+    fn normalization(&self) -> Normalization {
+        self.normalization
+    }
+
+    fn search<'a>(&'a self, query: &Query, strings: &'a StringTable) -> Option<Matches<'a>> {
+        match *query {
+            Query::Contains(key, mode) => Some(Box::new(self.search_iter(key, mode, strings))),
+            Query::Wildcard(pattern) => self
+                .wildcard_search_iter(pattern, strings)
+                .map(|matches| Box::new(matches) as Matches<'a>),
+            Query::Regex(regex) => self
+                .regex_search_iter(regex, strings)
+                .map(|matches| Box::new(matches) as Matches<'a>),
+            _ => None,
+        }
+    }
+
+    fn similar(&self, key: &str, similarity: Similarity, threshold: f32, strings: &StringTable) -> Option<Vec<(DocId, f32)>> {
+        Some(NGramIndex::similar(self, key, similarity, threshold, strings))
+    }
+
+    fn len(&self) -> usize {
+        self.words.as_ref().map_or(0, Vec::len)
+    }
+
+    fn stats(&self) -> Stats {
+        let grams = self.grams.iter().flatten();
+        Stats {
+            keys: self.grams.as_ref().map_or(0, HashMap::len),
+            postings: grams.map(|(_, ids)| ids.len()).sum(),
+            nodes: 0,
         }
     }
 }
+
+impl SearchStructure for NGramIndex {
+    fn store(&mut self, key: String, strings: &mut StringTable) {
+        NGramIndex::store(self, key, strings)
+    }
+
+    fn remove(&mut self, key: String, strings: &StringTable) -> bool {
+        NGramIndex::remove(self, key, strings)
+    }
+}
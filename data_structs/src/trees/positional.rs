@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use unicode_segmentation::UnicodeSegmentation;
+use bincode::{Decode, Encode};
+use crate::normalize::Normalization;
+use crate::strings::{DocId, StringTable};
+use crate::structure::{Matches, Phrases, Query, SearchStructure, Searchable, Stats};
+
+// Positional inverted index over lines: each folded word maps to the lines
+// holding it and where in them it sits, which answers phrase and NEAR/k
+// queries without reading the lines back
+#[derive(Clone, Encode, Decode, Debug)]
+pub struct PositionalIndex {
+    words: HashMap<String, Vec<(DocId, Vec<u32>)>>,  // sorted by line, positions sorted
+    lines: Vec<DocId>,                                // one entry per stored occurrence
+    normalization: Normalization,                     // applied to lines on store and to every query
+}
+
+// Lines holding a run of words and the position each run starts at
+type Runs = Vec<(DocId, Vec<u32>)>;
+
+impl Default for PositionalIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PositionalIndex {
+
+    pub fn new() -> Self {
+        Self::with_normalization(Normalization::default())
+    }
+
+    pub fn with_normalization(normalization: Normalization) -> Self {
+        Self {
+            words: HashMap::new(),
+            lines: Vec::new(),
+            normalization,
+        }
+    }
+
+    pub fn normalization(&self) -> Normalization {
+        self.normalization
+    }
+
+    pub fn store(&mut self, key: String, strings: &mut StringTable) {
+        let id = strings.intern(&key);
+        self.lines.push(id);
+
+        let folded = self.normalization.apply(&key);
+        for (position, word) in folded.unicode_words().enumerate() {
+            let position = position as u32;
+            let lines = self.words.entry(word.to_string()).or_default();
+            // Lines mostly arrive in id order, so this lands at the end
+            match lines.binary_search_by_key(&id, |(line, _)| *line) {
+                Ok(found) => {
+                    // A repeat of a stored line has the same positions
+                    let positions = &mut lines[found].1;
+                    if let Err(at) = positions.binary_search(&position) {
+                        positions.insert(at, position);
+                    }
+                }
+                Err(at) => lines.insert(at, (id, vec![position])),
+            }
+        }
+    }
+
+    // Forgets one occurrence of `key`; its positions go once no other
+    // occurrence of the same line is left
+    pub fn remove(&mut self, key: String, strings: &StringTable) -> bool {
+        let Some(id) = strings.id_of(&key) else {
+            return false;
+        };
+        let Some(position) = self.lines.iter().rposition(|&line| line == id) else {
+            return false;
+        };
+        self.lines.swap_remove(position);
+        if self.lines.contains(&id) {
+            return true;
+        }
+
+        let folded = self.normalization.apply(&key);
+        for word in folded.unicode_words() {
+            if let Some(lines) = self.words.get_mut(word) {
+                lines.retain(|(line, _)| *line != id);
+                if lines.is_empty() {
+                    self.words.remove(word);
+                }
+            }
+        }
+        true
+    }
+
+    pub fn update(&mut self, old: String, new: String, strings: &mut StringTable) -> bool {
+        let removed = self.remove(old, strings);
+        self.store(new, strings);
+        removed
+    }
+
+    pub fn phrase(&self, phrase: &str) -> Vec<(DocId, Vec<usize>)> {
+        let (runs, _) = self.runs(phrase);
+        runs.into_iter()
+            .map(|(id, starts)| (id, starts.into_iter().map(|start| start as usize).collect()))
+            .collect()
+    }
+
+    // Both phrases are found on their own, then every pair of runs in a
+    // shared line is measured from the last word of the earlier run to the
+    // first word of the later one. Overlapping runs do not count
+    pub fn near(&self, first: &str, second: &str, distance: usize) -> Vec<(DocId, Vec<usize>)> {
+        let (first, first_length) = self.runs(first);
+        let (second, second_length) = self.runs(second);
+        let mut results = Vec::new();
+
+        let mut others = second.iter().peekable();
+        for (id, starts) in first.iter() {
+            while others.next_if(|(other, _)| other < id).is_some() {}
+            let Some((_, other_starts)) = others.peek().filter(|(other, _)| other == id) else {
+                continue;
+            };
+
+            let mut positions = Vec::new();
+            for &start in starts.iter() {
+                for &other in other_starts.iter() {
+                    let (earlier, earlier_length, later) = if start < other {
+                        (start as usize, first_length, other as usize)
+                    } else {
+                        (other as usize, second_length, start as usize)
+                    };
+                    // Adjacent runs are 1 apart, like neighbouring words
+                    let end = earlier + earlier_length;
+                    if start != other && later >= end && later + 1 - end <= distance {
+                        positions.push(start as usize);
+                        positions.push(other as usize);
+                    }
+                }
+            }
+            if !positions.is_empty() {
+                positions.sort_unstable();
+                positions.dedup();
+                results.push((*id, positions));
+            }
+        }
+        results
+    }
+
+    // Where the folded words of `phrase` occur back to back, and how many
+    // words it has. Starts are found from the rarest word's positions and
+    // checked against the others, each lookup a binary search
+    fn runs(&self, phrase: &str) -> (Runs, usize) {
+        let folded = self.normalization.apply(phrase);
+        let words: Vec<&str> = folded.unicode_words().collect();
+        let lists: Option<Vec<&Runs>> = words.iter().map(|word| self.words.get(*word)).collect();
+        let Some(lists) = lists.filter(|lists| !lists.is_empty()) else {
+            return (Vec::new(), words.len());
+        };
+        let Some((rarest, shortest)) = lists.iter().enumerate().min_by_key(|(_, lines)| lines.len()) else {
+            return (Vec::new(), words.len());
+        };
+
+        let mut runs = Vec::new();
+        for (id, positions) in shortest.iter() {
+            // Positions of each word in this line, or `None` if one is missing
+            let line: Option<Vec<&Vec<u32>>> = lists
+                .iter()
+                .map(|lines| {
+                    let found = lines.binary_search_by_key(id, |(line, _)| *line).ok()?;
+                    Some(&lines[found].1)
+                })
+                .collect();
+            let Some(line) = line else {
+                continue;
+            };
+
+            let starts: Vec<u32> = positions
+                .iter()
+                .filter_map(|&position| position.checked_sub(rarest as u32))
+                .filter(|&start| {
+                    line.iter()
+                        .enumerate()
+                        .all(|(offset, positions)| positions.binary_search(&(start + offset as u32)).is_ok())
+                })
+                .collect();
+            if !starts.is_empty() {
+                runs.push((*id, starts));
+            }
+        }
+        (runs, words.len())
+    }
+}
+
+impl Searchable for PositionalIndex {
+    fn name(&self) -> &'static str {
+        "positional"
+    }
+
+    fn normalization(&self) -> Normalization {
+        self.normalization
+    }
+
+    // Only answers phrase lookups, see `phrases`
+    fn search<'a>(&'a self, _query: &Query, _strings: &'a StringTable) -> Option<Matches<'a>> {
+        None
+    }
+
+    fn phrases(&self) -> Option<&dyn Phrases> {
+        Some(self)
+    }
+
+    fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    fn stats(&self) -> Stats {
+        Stats {
+            keys: self.words.len(),
+            postings: self.words.values().flatten().map(|(_, positions)| positions.len()).sum(),
+            nodes: 0,
+        }
+    }
+}
+
+impl Phrases for PositionalIndex {
+    fn phrase(&self, phrase: &str) -> Vec<(DocId, Vec<usize>)> {
+        PositionalIndex::phrase(self, phrase)
+    }
+
+    fn near(&self, first: &str, second: &str, distance: usize) -> Vec<(DocId, Vec<usize>)> {
+        PositionalIndex::near(self, first, second, distance)
+    }
+}
+
+impl SearchStructure for PositionalIndex {
+    fn store(&mut self, key: String, strings: &mut StringTable) {
+        PositionalIndex::store(self, key, strings)
+    }
+
+    fn remove(&mut self, key: String, strings: &StringTable) -> bool {
+        PositionalIndex::remove(self, key, strings)
+    }
+}
@@ -0,0 +1,335 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use bincode::{Encode,Decode};
+use crate::normalize::Normalization;
+use crate::error::SearchError;
+use crate::strings::{DocId, StringTable};
+use crate::structure::{Matches, Query, SearchStructure, Searchable, Stats};
+
+#[derive(Clone, Encode, Decode, Debug)]
+pub struct RadixTree {
+    root: RadixNode,
+    normalization: Normalization,  // applied to keys on store and to every query
+}
+
+#[derive(Clone, Encode, Decode, Debug)]
+struct RadixNode {
+    label: String,  // edge label leading into this node, empty for the root
+    children: Vec<RadixNode>,  // sorted by the first char of their label
+    is_terminal: bool,
+    value: Option<Vec<DocId>>,  // one id per occurrence, pointing into the scope's StringTable
+    count:u32,
+}
+
+impl Default for RadixTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RadixNode {
+
+    fn with_label(label: String) -> Self {
+        Self {
+            label,
+            children: Vec::new(),
+            is_terminal: false,
+            value: None,
+            count: 0,
+        }
+    }
+
+    fn first_char(&self) -> Option<char> {
+        self.label.chars().next()
+    }
+
+    fn child_position(&self, char: char) -> Result<usize, usize> {
+        self.children.binary_search_by(|child| child.first_char().cmp(&Some(char)))
+    }
+
+    // Move everything below `at` (a byte offset into the label) into a new child
+    fn split(&mut self, at: usize) {
+        let mut tail = RadixNode::with_label(self.label.split_off(at));
+        tail.children = std::mem::take(&mut self.children);
+        tail.is_terminal = std::mem::take(&mut self.is_terminal);
+        tail.value = self.value.take();
+        tail.count = std::mem::take(&mut self.count);
+        self.children.push(tail);
+    }
+
+    // Undo a split once a node is neither a word nor a branching point
+    fn merge_single_child(&mut self) {
+        if self.is_terminal || self.children.len() != 1 {
+            return;
+        }
+        let Some(only) = self.children.pop() else {
+            return;
+        };
+        self.label.push_str(&only.label);
+        self.children = only.children;
+        self.is_terminal = only.is_terminal;
+        self.value = only.value;
+        self.count = only.count;
+    }
+
+    fn tally(&self, stats: &mut Stats) {
+        stats.nodes += 1;
+        if let Some(value) = &self.value {
+            stats.keys += 1;
+            stats.postings += value.len();
+        }
+        for child in self.children.iter() {
+            child.tally(stats);
+        }
+    }
+}
+
+impl RadixTree {
+
+    pub fn new() -> Self {
+        Self::with_normalization(Normalization::default())
+    }
+
+    pub fn with_normalization(normalization: Normalization) -> Self {
+        Self {
+            root: RadixNode::with_label(String::new()),
+            normalization,
+        }
+    }
+
+    pub fn normalization(&self) -> Normalization {
+        self.normalization
+    }
+
+    pub fn store(&mut self, key: String, strings: &mut StringTable) {
+        let id = strings.intern(&key);
+        let path = self.normalization.apply(&key);
+        let mut node = &mut self.root;
+        let mut rest = path.as_ref();
+
+        // Follow (and split where needed) the edges matching the key
+        while let Some(first) = rest.chars().next() {
+            let position = match node.child_position(first) {
+                Ok(position) => position,
+                Err(position) => {
+                    // Nothing shares this char, the rest of the key becomes one edge
+                    node.children.insert(position, RadixNode::with_label(rest.to_string()));
+                    node = &mut node.children[position];
+                    rest = "";
+                    continue;
+                }
+            };
+
+            let child = &mut node.children[position];
+            let common = common_prefix_len(&child.label, rest);
+            if common < child.label.len() {
+                child.split(common);
+            }
+            rest = &rest[common..];
+            node = &mut node.children[position];
+        }
+
+        // When we've consumed the whole key, mark this as a terminal node
+        if node.is_terminal {
+            // Word already exists, just increment count
+            match &mut node.value {
+                Some(x) => x.push(id),
+                None => panic!("Should be a value already")
+            }
+            node.count += 1;
+        } else {
+            // Word does not exist, store it as a new terminal node
+            node.is_terminal = true;
+            node.value = Some(vec![id]);
+            node.count = 1; // Start the count for this word at 1
+        }
+    }
+
+    // Forgets one occurrence of `key`, pruning and re-merging edges on the way up
+    pub fn remove(&mut self, key: String, strings: &StringTable) -> bool {
+        let Some(id) = strings.id_of(&key) else {
+            return false;
+        };
+        let path = self.normalization.apply(&key);
+        Self::remove_path(&mut self.root, &path, id)
+    }
+
+    fn remove_path(node: &mut RadixNode, rest: &str, id: DocId) -> bool {
+        let Some(first) = rest.chars().next() else {
+            let Some(value) = node.value.as_mut() else {
+                return false;
+            };
+            let Some(position) = value.iter().position(|&value| value == id) else {
+                return false;
+            };
+            value.remove(position);
+            node.count -= 1;
+            if value.is_empty() {
+                node.value = None;
+                node.is_terminal = false;
+            }
+            return true;
+        };
+
+        let Ok(position) = node.child_position(first) else {
+            return false;
+        };
+        let child = &mut node.children[position];
+        let Some(rest) = rest.strip_prefix(child.label.as_str()) else {
+            return false;
+        };
+        let removed = Self::remove_path(child, rest, id);
+        if removed {
+            if !child.is_terminal && child.children.is_empty() {
+                node.children.remove(position);
+            } else {
+                child.merge_single_child();
+            }
+        }
+        removed
+    }
+
+    pub fn update(&mut self, old: String, new: String, strings: &mut StringTable) -> bool {
+        let removed = self.remove(old, strings);
+        self.store(new, strings);
+        removed
+    }
+
+    pub fn search(&self, prefix: String) -> Result<Vec<DocId>, SearchError> {
+        let results: Vec<DocId> = self.search_iter(&prefix).collect();
+        if results.is_empty() {
+            Err(SearchError::NoMatch(prefix))
+        } else {
+            Ok(results)
+        }
+    }
+
+    // Lazy version of `search`, yielding shorter keys before longer ones so
+    // callers can stop after the first few
+    pub fn search_iter(&self, prefix: &str) -> impl Iterator<Item = DocId> + '_ {
+        let path = self.normalization.apply(prefix);
+        let mut below = Descendants::default();
+        match self.locate(&path) {
+            // The prefix ends inside an edge, so the node below is already longer
+            Some((node, true)) => below.push(node, path.chars().count()),
+            // The prefix itself is not a match, start from its children
+            Some((node, false)) => {
+                for child in node.children.iter() {
+                    below.push(child, path.chars().count());
+                }
+            }
+            None => {}
+        }
+        below
+    }
+
+    // The node whose path starts with `path`, and whether `path` ends inside its edge
+    fn locate(&self, path: &str) -> Option<(&RadixNode, bool)> {
+        let mut node = &self.root;
+        let mut rest = path;
+        let mut inside_edge = false;
+
+        // Traverse until the prefix is used up, possibly ending inside an edge
+        while let Some(first) = rest.chars().next() {
+            let child = &node.children[node.child_position(first).ok()?];
+
+            if child.label.starts_with(rest) {
+                inside_edge = child.label.len() > rest.len();
+                rest = "";
+            } else if rest.starts_with(&child.label) {
+                rest = &rest[child.label.len()..];
+            } else {
+                return None;
+            }
+            node = child;
+        }
+        Some((node, inside_edge))
+    }
+}
+
+// Walk over the ids stored in the pushed subtrees ordered by key length.
+// Edges carry labels of different lengths, so plain breadth-first order
+// would not be shortest first
+#[derive(Default)]
+struct Descendants<'a> {
+    nodes: Vec<&'a RadixNode>,
+    frontier: BinaryHeap<Reverse<(usize, usize)>>,  // (key length in chars, slot in `nodes`)
+    ids: std::slice::Iter<'a, DocId>,
+}
+
+impl<'a> Descendants<'a> {
+    // `depth` is the length of the key leading into `node`'s edge
+    fn push(&mut self, node: &'a RadixNode, depth: usize) {
+        self.nodes.push(node);
+        self.frontier.push(Reverse((depth + node.label.chars().count(), self.nodes.len() - 1)));
+    }
+}
+
+impl Iterator for Descendants<'_> {
+    type Item = DocId;
+
+    fn next(&mut self) -> Option<DocId> {
+        loop {
+            if let Some(&id) = self.ids.next() {
+                return Some(id);
+            }
+            let Reverse((depth, slot)) = self.frontier.pop()?;
+            let node = self.nodes[slot];
+            for child in node.children.iter() {
+                self.push(child, depth);
+            }
+            if let Some(value) = &node.value {
+                self.ids = value.iter();
+            }
+        }
+    }
+}
+
+// Length in bytes of the longest common prefix, always on a char boundary
+fn common_prefix_len(a: &str, b: &str) -> usize {
+    a.char_indices()
+        .zip(b.chars())
+        .find(|((_, x), y)| x != y)
+        .map_or_else(|| a.len().min(b.len()), |((index, _), _)| index)
+}
+
+impl Searchable for RadixTree {
+    fn name(&self) -> &'static str {
+        "radix"
+    }
+
+    fn normalization(&self) -> Normalization {
+        self.normalization
+    }
+
+    fn search<'a>(&'a self, query: &Query, _strings: &'a StringTable) -> Option<Matches<'a>> {
+        match *query {
+            Query::Prefix(prefix) => Some(Box::new(self.search_iter(prefix))),
+            _ => None,
+        }
+    }
+
+    fn shortest_first(&self, query: &Query) -> bool {
+        matches!(query, Query::Prefix(_))
+    }
+
+    fn len(&self) -> usize {
+        self.stats().postings
+    }
+
+    fn stats(&self) -> Stats {
+        let mut stats = Stats::default();
+        self.root.tally(&mut stats);
+        stats
+    }
+}
+
+impl SearchStructure for RadixTree {
+    fn store(&mut self, key: String, strings: &mut StringTable) {
+        RadixTree::store(self, key, strings)
+    }
+
+    fn remove(&mut self, key: String, strings: &StringTable) -> bool {
+        RadixTree::remove(self, key, strings)
+    }
+}
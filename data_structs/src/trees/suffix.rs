@@ -1,11 +1,23 @@
+use std::collections::{BTreeMap, VecDeque};
 use bincode::{Decode, Encode};
+use crate::normalize::Normalization;
+use crate::error::SearchError;
+use crate::strings::{DocId, StringTable};
+use crate::structure::{Matches, Query, SearchStructure, Searchable, Stats};
+use crate::wildcard::Pattern;
 
 #[derive(Clone, Encode, Decode, Debug)]
 pub struct SuffixTree {
-    children: [Option<Box<SuffixTree>>; 27], // 26 letters + space
+    root: SuffixNode,
+    normalization: Normalization, // applied to keys on store and to every query
+}
+
+#[derive(Clone, Encode, Decode, Debug, Default)]
+struct SuffixNode {
+    children: BTreeMap<char, SuffixNode>, // sparse, keyed by Unicode scalar value
     is_terminal: bool,
-    value: Option<Vec<String>>,
-    count: u16,
+    value: Option<Vec<DocId>>, // one id per occurrence of the (unreversed) key
+    count: u32,
 }
 
 impl Default for SuffixTree {
@@ -14,99 +26,240 @@ impl Default for SuffixTree {
     }
 }
 
+impl SuffixNode {
+    fn tally(&self, stats: &mut Stats) {
+        stats.nodes += 1;
+        if let Some(value) = &self.value {
+            stats.keys += 1;
+            stats.postings += value.len();
+        }
+        for child in self.children.values() {
+            child.tally(stats);
+        }
+    }
+}
+
 impl SuffixTree {
     pub fn new() -> Self {
+        Self::with_normalization(Normalization::default())
+    }
+
+    pub fn with_normalization(normalization: Normalization) -> Self {
         Self {
-            children: std::array::from_fn(|_| None),
-            is_terminal: false,
-            value: None,
-            count: 0,
+            root: SuffixNode::default(),
+            normalization,
         }
     }
 
-    pub fn store(&mut self, key: String) {
-        let mut node = self;
+    pub fn normalization(&self) -> Normalization {
+        self.normalization
+    }
+
+    pub fn store(&mut self, key: String, strings: &mut StringTable) {
+        let mut node = &mut self.root;
         let mut value = Vec::new();
-        let key: String = key.chars().rev().collect();
+        let id = strings.intern(&key);
+        let key: String = self.normalization.apply(&key).chars().rev().collect();
 
         // Traverse the Trie for each character of the key
         for char in key.chars() {
-            let lower = char.to_ascii_lowercase();
-            let index = match lower {
-                'a'..='z' => (lower as u8 - b'a') as usize,
-                ' ' => 26,     // Handle space characters
-                _ => continue, // Skip non-alphabetic characters
-            };
-
-            // If the node for this character doesn't exist, create a new one
-            if node.children[index].is_none() {
-                node.children[index] = Some(Box::new(SuffixTree::new()));
-            }
-
-            // Move to the next node in the Trie
-            node = node.children[index].as_mut().unwrap();
+            // Create the node for this character if needed and move into it
+            node = node.children.entry(char).or_default();
         }
 
         // When we've traversed all characters, mark this as a terminal node
         if node.is_terminal {
             // Word already exists, just increment count
             match &mut node.value {
-                Some(x) => x.push(key),
+                Some(x) => x.push(id),
                 None => panic!("Should be a value already"),
             }
             node.count += 1;
         } else {
             // Word does not exist, store it as a new terminal node
-            value.push(key);
+            value.push(id);
             node.is_terminal = true;
             node.value = Some(value);
             node.count = 1; // Start the count for this word at 1
         }
     }
 
-    pub fn search(&self, suffix: String) -> Result<Vec<String>, String> {
-        let mut node = self;
-        let suffix: String = suffix.chars().rev().collect(); // Reverse the suffix
+    // Forgets one occurrence of `key`, pruning nodes left without words
+    pub fn remove(&mut self, key: String, strings: &StringTable) -> bool {
+        let Some(id) = strings.id_of(&key) else {
+            return false;
+        };
+        let path: Vec<char> = self.normalization.apply(&key).chars().rev().collect();
+        Self::remove_path(&mut self.root, &path, id)
+    }
 
-        // Traverse to the end of the suffix
-        for char in suffix.chars() {
-            let index = if char == ' ' {
-                26
-            } else {
-                (char as u8 - b'a') as usize
+    fn remove_path(node: &mut SuffixNode, path: &[char], id: DocId) -> bool {
+        let Some((char, rest)) = path.split_first() else {
+            let Some(value) = node.value.as_mut() else {
+                return false;
             };
-
-            if node.children[index].is_none() {
-                return Err(format!("No words with suffix '{}'", suffix));
+            let Some(position) = value.iter().position(|&value| value == id) else {
+                return false;
+            };
+            value.remove(position);
+            node.count -= 1;
+            if value.is_empty() {
+                node.value = None;
+                node.is_terminal = false;
             }
-            node = node.children[index].as_ref().unwrap();
+            return true;
+        };
+
+        let Some(child) = node.children.get_mut(char) else {
+            return false;
+        };
+        let removed = Self::remove_path(child, rest, id);
+        if removed && !child.is_terminal && child.children.is_empty() {
+            node.children.remove(char);
         }
+        removed
+    }
 
-        // Collect all words from this node down
-        let mut results = Vec::new();
-        self.collect_words(node, &suffix, &mut results);
+    pub fn update(&mut self, old: String, new: String, strings: &mut StringTable) -> bool {
+        let removed = self.remove(old, strings);
+        self.store(new, strings);
+        removed
+    }
 
+    pub fn search(&self, suffix: String) -> Result<Vec<DocId>, SearchError> {
+        let results: Vec<DocId> = self.search_iter(&suffix).collect();
         if results.is_empty() {
-            Err("No words found".to_string())
+            Err(SearchError::NoMatch(suffix))
         } else {
             Ok(results)
         }
     }
 
-    fn collect_words(&self, node: &SuffixTree, prefix: &String, results: &mut Vec<String>) {
-        if node.is_terminal {
+    // Lazy version of `search`, yielding shorter keys before longer ones
+    pub fn search_iter(&self, suffix: &str) -> impl Iterator<Item = DocId> + '_ {
+        let reversed: String = self.normalization.apply(suffix).chars().rev().collect();
+
+        // The suffix itself is not a match, start from its children
+        let mut below = Descendants::default();
+        if let Some(node) = self.descend(&reversed) {
+            below.queue.extend(node.children.values());
+        }
+        below
+    }
+
+    // Keys matching a `?`/`*` pattern, walked from the end: a literal end
+    // prunes the walk to that suffix, a trailing `*` visits every node
+    pub fn wildcard_search_iter(&self, pattern: &str) -> impl Iterator<Item = DocId> + '_ {
+        let pattern = Pattern::parse(&self.normalization.apply(pattern)).reversed();
+        let states = pattern.start();
+        WildcardIter {
+            pattern,
+            stack: vec![(&self.root, states)],
+            ids: Default::default(),
+        }
+    }
+
+    fn descend(&self, reversed: &str) -> Option<&SuffixNode> {
+        let mut node = &self.root;
+        for char in reversed.chars() {
+            node = node.children.get(&char)?;
+        }
+        Some(node)
+    }
+}
+
+// Breadth-first walk over the ids stored in the queued subtrees
+#[derive(Default)]
+struct Descendants<'a> {
+    queue: VecDeque<&'a SuffixNode>,
+    ids: std::slice::Iter<'a, DocId>,
+}
+
+impl Iterator for Descendants<'_> {
+    type Item = DocId;
+
+    fn next(&mut self) -> Option<DocId> {
+        loop {
+            if let Some(&id) = self.ids.next() {
+                return Some(id);
+            }
+            let node = self.queue.pop_front()?;
+            self.queue.extend(node.children.values());
             if let Some(value) = &node.value {
-                for word in value {
-                    // Only add words that start with prefix but aren't equal to it
-                    if word.starts_with(prefix) && word != prefix {
-                        results.push(word.chars().rev().collect());
-                    }
+                self.ids = value.iter();
+            }
+        }
+    }
+}
+
+// Depth-first walk of the pattern NFA along the reversed keys, in sorted key order;
+// a branch is dropped as soon as no pattern state survives its edge
+struct WildcardIter<'a> {
+    pattern: Pattern,
+    stack: Vec<(&'a SuffixNode, Vec<usize>)>,
+    ids: std::slice::Iter<'a, DocId>,
+}
+
+impl<'a> Iterator for WildcardIter<'a> {
+    type Item = DocId;
+
+    fn next(&mut self) -> Option<DocId> {
+        loop {
+            if let Some(&id) = self.ids.next() {
+                return Some(id);
+            }
+            let (node, states) = self.stack.pop()?;
+            for (&char, child) in node.children.iter().rev() {
+                let next = self.pattern.step(&states, char);
+                if !next.is_empty() {
+                    self.stack.push((child, next));
                 }
             }
+            if let Some(value) = node.value.as_ref().filter(|_| self.pattern.accepts(&states)) {
+                self.ids = value.iter();
+            }
         }
+    }
+}
+
+impl Searchable for SuffixTree {
+    fn name(&self) -> &'static str {
+        "suffix"
+    }
+
+    fn normalization(&self) -> Normalization {
+        self.normalization
+    }
 
-        for child in node.children.iter().flatten() {
-            self.collect_words(child, prefix, results);
+    fn search<'a>(&'a self, query: &Query, _strings: &'a StringTable) -> Option<Matches<'a>> {
+        match *query {
+            Query::Suffix(suffix) => Some(Box::new(self.search_iter(suffix))),
+            Query::Wildcard(pattern) => Some(Box::new(self.wildcard_search_iter(pattern))),
+            _ => None,
         }
     }
+
+    fn shortest_first(&self, query: &Query) -> bool {
+        matches!(query, Query::Suffix(_))
+    }
+
+    fn len(&self) -> usize {
+        self.stats().postings
+    }
+
+    fn stats(&self) -> Stats {
+        let mut stats = Stats::default();
+        self.root.tally(&mut stats);
+        stats
+    }
+}
+
+impl SearchStructure for SuffixTree {
+    fn store(&mut self, key: String, strings: &mut StringTable) {
+        SuffixTree::store(self, key, strings)
+    }
+
+    fn remove(&mut self, key: String, strings: &StringTable) -> bool {
+        SuffixTree::remove(self, key, strings)
+    }
 }
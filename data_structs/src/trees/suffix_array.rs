@@ -0,0 +1,547 @@
+use std::collections::{HashMap, HashSet};
+use bincode::{Decode, Encode};
+use regex::Regex;
+use crate::normalize::Normalization;
+use crate::prefilter;
+use crate::error::SearchError;
+use crate::strings::{DocId, StringTable};
+use crate::structure::{ContainsMode, Matches, Query, SearchStructure, Searchable, Stats, Substrings};
+
+// Byte between entries in `text`. Folded keys never contain it, so no match
+// can run from one entry into the next
+pub(crate) const SEPARATOR: u8 = 0;
+
+// Suffix array with LCP over the concatenated folded text of a scope. Every
+// distinct string is stored once; `store` only appends, the suffixes are
+// sorted by `finish` once the build is done
+#[derive(Clone, Encode, Decode, Debug)]
+pub struct SuffixArray {
+    text: Vec<u8>,                  // folded entries, each followed by SEPARATOR
+    starts: Vec<u32>,               // where each entry begins in `text`
+    ids: Vec<DocId>,                // string of each entry, parallel to `starts`
+    occurrences: Vec<u32>,          // times each entry was stored
+    entries: HashMap<DocId, u32>,   // entry of each stored string
+    suffixes: Vec<u32>,             // every position of `text`, in suffix order
+    lcp: Vec<u32>,                  // common prefix of each suffix with the one before
+    normalization: Normalization,   // applied to keys on store and to every query
+}
+
+impl Default for SuffixArray {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SuffixArray {
+
+    pub fn new() -> Self {
+        Self::with_normalization(Normalization::default())
+    }
+
+    pub fn with_normalization(normalization: Normalization) -> Self {
+        Self {
+            text: Vec::new(),
+            starts: Vec::new(),
+            ids: Vec::new(),
+            occurrences: Vec::new(),
+            entries: HashMap::new(),
+            suffixes: Vec::new(),
+            lcp: Vec::new(),
+            normalization,
+        }
+    }
+
+    pub fn normalization(&self) -> Normalization {
+        self.normalization
+    }
+
+    pub fn store(&mut self, key: String, strings: &mut StringTable) {
+        let id = strings.intern(&key);
+        if let Some(&entry) = self.entries.get(&id) {
+            self.occurrences[entry as usize] += 1;
+            return;
+        }
+
+        self.entries.insert(id, self.ids.len() as u32);
+        self.starts.push(self.text.len() as u32);
+        self.ids.push(id);
+        self.occurrences.push(1);
+        self.text.extend_from_slice(self.normalization.apply(&key).as_bytes());
+        self.text.push(SEPARATOR);
+    }
+
+    // Forgets one occurrence of `key`. Dropping the last one rebuilds the
+    // whole array, so removals are meant to be rare
+    pub fn remove(&mut self, key: String, strings: &StringTable) -> bool {
+        let Some(emptied) = self.forget(&key, strings) else {
+            return false;
+        };
+        if emptied {
+            self.finish();
+        }
+        true
+    }
+
+    pub fn update(&mut self, old: String, new: String, strings: &mut StringTable) -> bool {
+        let removed = self.forget(&old, strings).is_some();
+        self.store(new, strings);
+        self.finish();
+        removed
+    }
+
+    // `remove` without the rebuild; whether the entry's text was dropped,
+    // `None` when `key` was not stored
+    fn forget(&mut self, key: &str, strings: &StringTable) -> Option<bool> {
+        let &entry = strings.id_of(key).and_then(|id| self.entries.get(&id))?;
+        let entry = entry as usize;
+        self.occurrences[entry] -= 1;
+        if self.occurrences[entry] > 0 {
+            return Some(false);
+        }
+
+        let (start, end) = self.span(entry);
+        self.text.drain(start..=end);
+        let removed = (end + 1 - start) as u32;
+        self.starts.remove(entry);
+        self.ids.remove(entry);
+        self.occurrences.remove(entry);
+        for start in self.starts[entry..].iter_mut() {
+            *start -= removed;
+        }
+        self.entries = self.ids.iter().enumerate().map(|(entry, &id)| (id, entry as u32)).collect();
+        Some(true)
+    }
+
+    // Sorts the suffixes by prefix doubling, O(n log² n), then derives the
+    // LCP array with Kasai's algorithm
+    pub fn finish(&mut self) {
+        self.suffixes = sort_suffixes(&self.text);
+        self.lcp = kasai(&self.text, &self.suffixes);
+    }
+
+    // Entries holding `key` anywhere, the default Contains mode; see
+    // `search_iter` for strict infix matches
+    pub fn search(&self, key: String) -> Result<Vec<DocId>, SearchError> {
+        if key.is_empty() {
+            return Err(SearchError::InvalidQuery("an empty string is contained everywhere".to_string()));
+        }
+        let results: Vec<DocId> = self.search_iter(&key, ContainsMode::default()).collect();
+
+        if results.is_empty() {
+            Err(SearchError::NoMatch(key))
+        } else {
+            Ok(results)
+        }
+    }
+
+    // Lazy version of `search`, in suffix order; each id is yielded once
+    pub fn search_iter(&self, key: &str, mode: ContainsMode) -> impl Iterator<Item = DocId> + '_ {
+        let key = self.normalization.apply(key).into_owned();
+        let mut seen = HashSet::new();
+
+        self.matching(key.as_bytes()).iter().filter_map(move |&position| {
+            let entry = self.entry_at(position);
+            if !seen.insert(entry) {
+                return None;
+            }
+            let (start, end) = self.span(entry);
+            let text = &self.text[start..end];
+            let inside = mode.allows_ends() || (!text.starts_with(key.as_bytes()) && !text.ends_with(key.as_bytes()));
+            inside.then_some(self.ids[entry])
+        })
+    }
+
+    // Strings the regex matches, run only on those holding each of its
+    // required literals anywhere, in string table order. Works for lines
+    // too, unlike the word grams; `None` when the regex has no literal
+    pub fn regex_search_iter<'a>(&'a self, regex: &Regex, strings: &'a StringTable) -> Option<impl Iterator<Item = DocId> + 'a> {
+        let literals: Vec<String> = prefilter::required_literals(regex.as_str())
+            .into_iter()
+            .map(|literal| self.normalization.apply(&literal).into_owned())
+            .collect();
+        let candidates = prefilter::candidates(&literals, |literal| self.search_iter(literal, ContainsMode::Anywhere))?;
+
+        let regex = regex.clone(); // shares the compiled program
+        Some(candidates.into_iter().filter(move |&id| {
+            strings.resolve(id).is_some_and(|item| regex.is_match(item))
+        }))
+    }
+
+    // How often `key` occurs across everything stored, counting repeated
+    // strings once per time they were stored
+    pub fn count(&self, key: &str) -> usize {
+        let key = self.normalization.apply(key);
+        self.matching(key.as_bytes())
+            .iter()
+            .map(|&position| self.occurrences[self.entry_at(position)] as usize)
+            .sum()
+    }
+
+    // Every place `key` occurs as (string, byte offset into its folded
+    // text), ordered by string then offset
+    pub fn positions(&self, key: &str) -> Vec<(DocId, usize)> {
+        let key = self.normalization.apply(key);
+        let mut positions: Vec<(DocId, usize)> = self
+            .matching(key.as_bytes())
+            .iter()
+            .map(|&position| {
+                let entry = self.entry_at(position);
+                (self.ids[entry], (position - self.starts[entry]) as usize)
+            })
+            .collect();
+        positions.sort_unstable();
+        positions
+    }
+
+    // Longest substring found at least twice: either the longest common
+    // prefix of two suffixes, or a whole string that was stored twice
+    pub fn longest_repeated(&self) -> Option<(String, usize)> {
+        let mut best: Option<(usize, usize)> = None; // (length, position)
+        let mut keep = |length: usize, position: usize| {
+            if length > 0 && best.is_none_or(|(best, _)| length > best) {
+                best = Some((length, position));
+            }
+        };
+
+        // Common prefix with the previous suffix that starts on a char
+        // boundary; ones starting inside a char only narrow the minimum
+        let mut since: Option<usize> = None;
+        for (row, &position) in self.suffixes.iter().enumerate() {
+            let position = position as usize;
+            since = since.map(|common| common.min(self.lcp[row] as usize));
+            if !is_boundary(&self.text, position) {
+                continue;
+            }
+            if let Some(common) = since {
+                keep(char_floor(&self.text, position, common), position);
+            }
+            since = Some(usize::MAX);
+        }
+        for (entry, &count) in self.occurrences.iter().enumerate() {
+            if count > 1 {
+                let (start, end) = self.span(entry);
+                keep(end - start, start);
+            }
+        }
+
+        let (length, position) = best?;
+        let text = &self.text[position..position + length];
+        let count = self.matching(text).iter().map(|&position| self.occurrences[self.entry_at(position)] as usize).sum();
+        Some((String::from_utf8_lossy(text).into_owned(), count))
+    }
+
+    // Suffixes sharing their first `length` chars sit next to each other, so
+    // one pass over the array counts every distinct substring of that length
+    pub fn most_frequent(&self, length: usize, n: usize) -> Vec<(String, usize)> {
+        let mut groups: Vec<(usize, &[u8])> = Vec::new();
+        for &position in self.suffixes.iter() {
+            let position = position as usize;
+            let Some(end) = chars_end(&self.text, position, length) else {
+                continue;
+            };
+            let substring = &self.text[position..end];
+            let count = self.occurrences[self.entry_at(position as u32)] as usize;
+            match groups.last_mut() {
+                Some((total, last)) if *last == substring => *total += count,
+                _ => groups.push((count, substring)),
+            }
+        }
+
+        groups.sort_unstable_by(|(first, first_text), (second, second_text)| {
+            second.cmp(first).then(first_text.cmp(second_text))
+        });
+        groups
+            .into_iter()
+            .take(n)
+            .map(|(count, text)| (String::from_utf8_lossy(text).into_owned(), count))
+            .collect()
+    }
+
+    // The run of suffixes starting with `pattern`: two binary searches, each
+    // comparing at most `pattern.len()` bytes per step. A pattern holding
+    // the separator would match across entries, so it matches nothing
+    fn matching(&self, pattern: &[u8]) -> &[u32] {
+        if pattern.contains(&SEPARATOR) {
+            return &[];
+        }
+        let suffix = |position: u32| &self.text[position as usize..];
+        let start = self.suffixes.partition_point(|&position| suffix(position) < pattern);
+        let length = self.suffixes[start..].partition_point(|&position| suffix(position).starts_with(pattern));
+        &self.suffixes[start..start + length]
+    }
+
+    fn entry_at(&self, position: u32) -> usize {
+        self.starts.partition_point(|&start| start <= position) - 1
+    }
+
+    // Where an entry's text starts and where its separator sits
+    fn span(&self, entry: usize) -> (usize, usize) {
+        let start = self.starts[entry] as usize;
+        let end = self.starts.get(entry + 1).map_or(self.text.len(), |&next| next as usize) - 1;
+        (start, end)
+    }
+}
+
+// Longest substring of both texts, compared as given
+pub fn longest_common_substring(first: &str, second: &str) -> String {
+    let mut text = first.as_bytes().to_vec();
+    text.push(SEPARATOR);
+    let split = text.len();
+    text.extend_from_slice(second.as_bytes());
+    text.push(SEPARATOR);
+    let suffixes = sort_suffixes(&text);
+    let lcp = kasai(&text, &suffixes);
+
+    // Per text, the common prefix with its latest suffix seen so far; the
+    // best pair always sits next to each other once the other text's
+    // suffixes in between are skipped
+    let mut since: [Option<usize>; 2] = [None, None];
+    let mut best = (0, 0);
+    for (row, &position) in suffixes.iter().enumerate() {
+        let position = position as usize;
+        for common in since.iter_mut().flatten() {
+            *common = (*common).min(lcp[row] as usize);
+        }
+        if !is_boundary(&text, position) {
+            continue;
+        }
+        let side = (position >= split) as usize;
+        if let Some(common) = since[1 - side] {
+            let common = char_floor(&text, position, common);
+            if common > best.0 {
+                best = (common, position);
+            }
+        }
+        since[side] = Some(usize::MAX);
+    }
+
+    let (length, position) = best;
+    String::from_utf8_lossy(&text[position..position + length]).into_owned()
+}
+
+// Whether a char starts at `position`, i.e. it is no UTF-8 continuation byte
+fn is_boundary(text: &[u8], position: usize) -> bool {
+    text.get(position).is_none_or(|&byte| (byte as i8) >= -0x40)
+}
+
+// `length` cut back so the bytes from `position` end on a char boundary
+fn char_floor(text: &[u8], position: usize, mut length: usize) -> usize {
+    while length > 0 && !is_boundary(text, position + length) {
+        length -= 1;
+    }
+    length
+}
+
+// Where `length` chars from `position` end, `None` if a separator or the
+// end of the text comes first or `position` is inside a char
+fn chars_end(text: &[u8], position: usize, length: usize) -> Option<usize> {
+    if length == 0 || !is_boundary(text, position) {
+        return None;
+    }
+    let mut end = position;
+    for _ in 0..length {
+        if *text.get(end)? == SEPARATOR {
+            return None;
+        }
+        end += 1;
+        while !is_boundary(text, end) {
+            end += 1;
+        }
+    }
+    Some(end)
+}
+
+pub(crate) fn sort_suffixes(text: &[u8]) -> Vec<u32> {
+    let length = text.len();
+    if length == 0 {
+        return Vec::new();
+    }
+    let mut suffixes: Vec<u32> = (0..length as u32).collect();
+    let mut rank: Vec<u32> = text.iter().map(|&byte| byte as u32).collect();
+    let mut next = vec![0; length];
+    let mut step = 1;
+
+    loop {
+        // Rank of the first `step` bytes, then of the `step` after them;
+        // running off the end sorts before any byte
+        let key = |position: u32| {
+            let position = position as usize;
+            (rank[position], rank.get(position + step).map_or(0, |rank| rank + 1))
+        };
+        suffixes.sort_unstable_by_key(|&position| key(position));
+
+        next[suffixes[0] as usize] = 0;
+        for pair in suffixes.windows(2) {
+            let bump = (key(pair[0]) != key(pair[1])) as u32;
+            next[pair[1] as usize] = next[pair[0] as usize] + bump;
+        }
+        std::mem::swap(&mut rank, &mut next);
+
+        // Every suffix has its own rank, the order is final
+        if rank[suffixes[length - 1] as usize] as usize == length - 1 {
+            break;
+        }
+        step *= 2;
+    }
+    suffixes
+}
+
+// lcp[i] is the common prefix of suffixes[i - 1] and suffixes[i]. A shared
+// prefix never runs across a separator, since no match can either
+fn kasai(text: &[u8], suffixes: &[u32]) -> Vec<u32> {
+    let mut rank = vec![0; suffixes.len()];
+    for (index, &position) in suffixes.iter().enumerate() {
+        rank[position as usize] = index;
+    }
+
+    let mut lcp = vec![0; suffixes.len()];
+    let mut common = 0;
+    for position in 0..text.len() {
+        if rank[position] == 0 {
+            common = 0;
+            continue;
+        }
+        let previous = suffixes[rank[position] - 1] as usize;
+        while position + common < text.len()
+            && previous + common < text.len()
+            && text[position + common] == text[previous + common]
+            && text[position + common] != SEPARATOR
+        {
+            common += 1;
+        }
+        lcp[rank[position]] = common as u32;
+        common = common.saturating_sub(1);
+    }
+    lcp
+}
+
+impl Searchable for SuffixArray {
+    fn name(&self) -> &'static str {
+        "suffix-array"
+    }
+
+    fn normalization(&self) -> Normalization {
+        self.normalization
+    }
+
+    fn search<'a>(&'a self, query: &Query, strings: &'a StringTable) -> Option<Matches<'a>> {
+        match *query {
+            Query::Contains(key, mode) => Some(Box::new(self.search_iter(key, mode))),
+            Query::Regex(regex) => self
+                .regex_search_iter(regex, strings)
+                .map(|matches| Box::new(matches) as Matches<'a>),
+            _ => None,
+        }
+    }
+
+    fn count(&self, term: &str) -> Option<usize> {
+        Some(SuffixArray::count(self, term))
+    }
+
+    fn positions(&self, term: &str) -> Option<Vec<(DocId, usize)>> {
+        Some(SuffixArray::positions(self, term))
+    }
+
+    fn substrings(&self) -> Option<&dyn Substrings> {
+        Some(self)
+    }
+
+    fn len(&self) -> usize {
+        self.occurrences.iter().map(|&count| count as usize).sum()
+    }
+
+    fn stats(&self) -> Stats {
+        Stats {
+            keys: self.ids.len(),
+            postings: self.suffixes.len(),
+            nodes: 0,
+        }
+    }
+}
+
+impl Substrings for SuffixArray {
+    fn longest_repeated(&self) -> Option<(String, usize)> {
+        SuffixArray::longest_repeated(self)
+    }
+
+    fn most_frequent(&self, length: usize, n: usize) -> Vec<(String, usize)> {
+        SuffixArray::most_frequent(self, length, n)
+    }
+}
+
+impl SearchStructure for SuffixArray {
+    fn store(&mut self, key: String, strings: &mut StringTable) {
+        SuffixArray::store(self, key, strings)
+    }
+
+    fn remove(&mut self, key: String, strings: &StringTable) -> bool {
+        SuffixArray::remove(self, key, strings)
+    }
+
+    fn finish(&mut self) {
+        SuffixArray::finish(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{corpus, naive, patterns};
+
+    fn build(strings: &[String]) -> (SuffixArray, StringTable) {
+        let mut table = StringTable::new();
+        let mut suffix_array = SuffixArray::new();
+        for string in strings.iter() {
+            suffix_array.store(string.clone(), &mut table);
+        }
+        suffix_array.finish();
+        (suffix_array, table)
+    }
+
+    #[test]
+    fn count_and_positions_match_a_naive_scan() {
+        let strings = corpus(7);
+        let (suffix_array, table) = build(&strings);
+        let folding = suffix_array.normalization();
+
+        for pattern in patterns() {
+            let folded = folding.apply(&pattern);
+            let count: usize = strings.iter().map(|string| naive(&folding.apply(string), &folded).len()).sum();
+            assert_eq!(suffix_array.count(&pattern), count, "count of {:?}", pattern);
+
+            let mut positions: Vec<(DocId, usize)> = (0..table.len() as DocId)
+                .flat_map(|id| {
+                    let text = folding.apply(table.resolve(id).unwrap()).into_owned();
+                    naive(&text, &folded).into_iter().map(move |offset| (id, offset))
+                })
+                .collect();
+            positions.sort_unstable();
+            assert_eq!(suffix_array.positions(&pattern), positions, "positions of {:?}", pattern);
+        }
+    }
+
+    #[test]
+    fn removals_are_reflected_in_counts() {
+        let mut strings = corpus(7);
+        let (mut suffix_array, table) = build(&strings);
+        for removed in ["banana", "bandana"] {
+            assert!(suffix_array.remove(removed.to_string(), &table));
+            let at = strings.iter().position(|string| string == removed).unwrap();
+            strings.remove(at);
+        }
+
+        for pattern in ["ban", "ana", "nd", "a"] {
+            let folding = suffix_array.normalization();
+            let count: usize = strings.iter().map(|string| naive(&folding.apply(string), pattern).len()).sum();
+            assert_eq!(suffix_array.count(pattern), count, "count of {:?}", pattern);
+        }
+    }
+
+    #[test]
+    fn patterns_never_match_across_entries() {
+        let (suffix_array, _) = build(&["ab".to_string(), "cd".to_string()]);
+        assert_eq!(suffix_array.count("b\0c"), 0);
+        assert_eq!(suffix_array.count("\0"), 0);
+        assert!(suffix_array.positions("b\0c").is_empty());
+    }
+}
@@ -1,11 +1,26 @@
+use std::collections::{BTreeMap, BinaryHeap, VecDeque};
+use std::ops::Bound;
 use bincode::{Encode,Decode};
+use crate::normalize::Normalization;
+use crate::error::SearchError;
+use crate::strings::{DocId, StringTable};
+use crate::structure::{Matches, Query, SearchStructure, Searchable, Stats};
+use crate::wildcard::Pattern;
 
 #[derive(Clone, Encode, Decode, Debug)]
 pub struct Trie {
-    children: [Option<Box<Trie>>; 27],  // 26 letters + space
+    root: TrieNode,
+    normalization: Normalization,  // applied to keys on store and to every query
+}
+
+#[derive(Clone, Encode, Decode, Debug, Default)]
+struct TrieNode {
+    children: BTreeMap<char, TrieNode>, // sparse, keyed by Unicode scalar value
     is_terminal: bool,
-    value: Option<Vec<String>>,
-    count:u16,
+    value: Option<Vec<DocId>>,  // one id per occurrence, pointing into the scope's StringTable
+    count:u32,
+    weight: u32,      // ranking weight: occurrences plus any imported frequency
+    max_weight: u32,  // largest weight anywhere in this subtree
 }
 
 impl Default for Trie {
@@ -14,94 +29,596 @@ impl Default for Trie {
     }
 }
 
+impl TrieNode {
+
+    fn descend(&self, path: &str) -> Option<&TrieNode> {
+        let mut node = self;
+        for char in path.chars() {
+            node = node.children.get(&char)?;
+        }
+        Some(node)
+    }
+
+    fn refresh_max_weight(&mut self) {
+        let children = self.children.values().map(|child| child.max_weight).max();
+        self.max_weight = children.unwrap_or(0).max(self.weight);
+    }
+
+    fn tally(&self, stats: &mut Stats) {
+        stats.nodes += 1;
+        if let Some(value) = &self.value {
+            stats.keys += 1;
+            stats.postings += value.len();
+        }
+        for child in self.children.values() {
+            child.tally(stats);
+        }
+    }
+}
+
 impl Trie {
 
     pub fn new() -> Self {
+        Self::with_normalization(Normalization::default())
+    }
+
+    pub fn with_normalization(normalization: Normalization) -> Self {
         Self {
-            children: std::array::from_fn(|_| None),
-            is_terminal: false,
-            value: None,
-            count: 0,
+            root: TrieNode::default(),
+            normalization,
         }
     }
-    
-    pub fn store(&mut self, key: String) {
-        let mut node = self;
-        let mut value = Vec::new();
 
-        // Traverse the Trie for each character of the key
-        for char in key.chars() {
-            let lower = char.to_ascii_lowercase();
-            let index = match lower {
-                'a'..='z' => (lower as u8 - b'a') as usize,
-                ' ' => 26,  // Handle space characters
-                _ => continue,  // Skip non-alphabetic characters
-            };
+    pub fn normalization(&self) -> Normalization {
+        self.normalization
+    }
 
-            // If the node for this character doesn't exist, create a new one
-            if node.children[index].is_none() {
-                node.children[index] = Some(Box::new(Trie::new()));
-            }
+    pub fn store(&mut self, key: String, strings: &mut StringTable) {
+        let id = strings.intern(&key);
+        let path = self.normalization.apply(&key);
+        let mut node = &mut self.root;
+        let mut value = Vec::new();
 
-            // Move to the next node in the Trie
-            node = node.children[index].as_mut().unwrap();
+        // Traverse the Trie for each character of the key
+        for char in path.chars() {
+            // Create the node for this character if needed and move into it
+            node = node.children.entry(char).or_default();
         }
 
         // When we've traversed all characters, mark this as a terminal node
         if node.is_terminal {
             // Word already exists, just increment count
             match &mut node.value {
-                Some(x) => x.push(key),
+                Some(x) => x.push(id),
                 None => panic!("Should be a value already")
             }
             node.count += 1;
         } else {
             // Word does not exist, store it as a new terminal node
-            value.push(key);
+            value.push(id);
             node.is_terminal = true;
             node.value = Some(value);
             node.count = 1; // Start the count for this word at 1
         }
+        node.weight = node.weight.saturating_add(1);
+
+        let weight = node.weight;
+        Self::raise_max_weight(&mut self.root, &path, weight);
     }
 
-    pub fn search(&self, prefix: String) -> Result<Vec<String>, String> {
-        let mut node = self;
-        
-        // Traverse to the end of the prefix
-        for char in prefix.chars() {
-            let index = if char == ' ' { 26 } else { (char as u8 - b'a') as usize };
-            
-            if node.children[index].is_none() {
-                return Err(format!("No words with prefix '{}'", prefix));
-            }
-            node = node.children[index].as_ref().unwrap();
-        }
-        
-        // Collect all words from this node down
-        let mut results = Vec::new();
-        self.collect_words(node, &prefix, &mut results);
-        
+    // Seeds the ranking weight of an already stored key, e.g. from a frequency list
+    pub fn add_weight(&mut self, key: &str, weight: u32) -> bool {
+        let path = self.normalization.apply(key);
+        let mut node = &mut self.root;
+        for char in path.chars() {
+            match node.children.get_mut(&char) {
+                Some(child) => node = child,
+                None => return false,
+            }
+        }
+        if !node.is_terminal {
+            return false;
+        }
+        node.weight = node.weight.saturating_add(weight);
+
+        let weight = node.weight;
+        Self::raise_max_weight(&mut self.root, &path, weight);
+        true
+    }
+
+    fn raise_max_weight(root: &mut TrieNode, path: &str, weight: u32) {
+        let mut node = root;
+        node.max_weight = node.max_weight.max(weight);
+        for char in path.chars() {
+            match node.children.get_mut(&char) {
+                Some(child) => node = child,
+                None => return,
+            }
+            node.max_weight = node.max_weight.max(weight);
+        }
+    }
+
+    // Forgets one occurrence of `key`, pruning nodes left without words
+    pub fn remove(&mut self, key: String, strings: &StringTable) -> bool {
+        let Some(id) = strings.id_of(&key) else {
+            return false;
+        };
+        let path: Vec<char> = self.normalization.apply(&key).chars().collect();
+        Self::remove_path(&mut self.root, &path, id)
+    }
+
+    fn remove_path(node: &mut TrieNode, path: &[char], id: DocId) -> bool {
+        let Some((char, rest)) = path.split_first() else {
+            let Some(value) = node.value.as_mut() else {
+                return false;
+            };
+            let Some(position) = value.iter().position(|&value| value == id) else {
+                return false;
+            };
+            value.remove(position);
+            node.count -= 1;
+            node.weight = node.weight.saturating_sub(1);
+            if value.is_empty() {
+                node.value = None;
+                node.is_terminal = false;
+                node.weight = 0;
+            }
+            node.refresh_max_weight();
+            return true;
+        };
+
+        let Some(child) = node.children.get_mut(char) else {
+            return false;
+        };
+        let removed = Self::remove_path(child, rest, id);
+        if removed && !child.is_terminal && child.children.is_empty() {
+            node.children.remove(char);
+        }
+        if removed {
+            node.refresh_max_weight();
+        }
+        removed
+    }
+
+    pub fn update(&mut self, old: String, new: String, strings: &mut StringTable) -> bool {
+        let removed = self.remove(old, strings);
+        self.store(new, strings);
+        removed
+    }
+
+    pub fn search(&self, prefix: String) -> Result<Vec<DocId>, SearchError> {
+        let results: Vec<DocId> = self.search_iter(&prefix).collect();
         if results.is_empty() {
-            Err("No words found".to_string())
+            Err(SearchError::NoMatch(prefix))
         } else {
             Ok(results)
         }
     }
-    
-    fn collect_words(&self, node: &Trie, prefix: &String, results: &mut Vec<String>) {
-        if node.is_terminal {
+
+    // Lazy version of `search`, yielding shorter keys before longer ones so
+    // callers can stop after the first few
+    pub fn search_iter(&self, prefix: &str) -> impl Iterator<Item = DocId> + '_ {
+        // The prefix itself is not a match, start from its children
+        let mut below = Descendants::default();
+        if let Some(node) = self.root.descend(&self.normalization.apply(prefix)) {
+            below.queue.extend(node.children.values());
+        }
+        below
+    }
+
+    // The `k` heaviest keys starting with `prefix`, heaviest first. Nodes are
+    // expanded best-first on their cached `max_weight`, so the walk stops as
+    // soon as no unexplored subtree can beat the keys already found
+    pub fn top_k(&self, prefix: &str, k: usize) -> Vec<(DocId, u32)> {
+        let Some(node) = self.root.descend(&self.normalization.apply(prefix)) else {
+            return Vec::new();
+        };
+
+        // (bound, is a finished key, slot in `nodes`), keys win ties with subtrees
+        let mut nodes = vec![node];
+        let mut frontier = BinaryHeap::from([(node.max_weight, false, 0)]);
+        let mut results = Vec::with_capacity(k);
+
+        while results.len() < k {
+            let Some((weight, is_key, slot)) = frontier.pop() else {
+                break;
+            };
+            let node = nodes[slot];
+            if is_key {
+                if let Some(&id) = node.value.as_ref().and_then(|value| value.first()) {
+                    results.push((id, weight));
+                }
+                continue;
+            }
+
+            if node.is_terminal {
+                frontier.push((node.weight, true, slot));
+            }
+            for child in node.children.values() {
+                nodes.push(child);
+                frontier.push((child.max_weight, false, nodes.len() - 1));
+            }
+        }
+        results
+    }
+
+    // Every stored key in sorted order, with the ids of its occurrences.
+    // Children are kept in a BTreeMap, so a pre-order walk is already sorted
+    pub fn iter(&self) -> impl Iterator<Item = (String, &[DocId])> + '_ {
+        self.range(Bound::Unbounded, Bound::Unbounded)
+    }
+
+    // Keys between the bounds in sorted order; bounds are folded like keys
+    pub fn range(&self, start: Bound<&str>, end: Bound<&str>) -> impl Iterator<Item = (String, &[DocId])> + '_ {
+        let end = match end {
+            Bound::Included(end) => Bound::Included(self.normalization.apply(end).into_owned()),
+            Bound::Excluded(end) => Bound::Excluded(self.normalization.apply(end).into_owned()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let mut ordered = Ordered { stack: Vec::new(), end };
+
+        let (start, inclusive) = match start {
+            Bound::Included(start) => (self.normalization.apply(start), true),
+            Bound::Excluded(start) => (self.normalization.apply(start), false),
+            Bound::Unbounded => {
+                ordered.stack.push((String::new(), &self.root));
+                return ordered;
+            }
+        };
+
+        // Seek to `start`: every sibling after the path is entirely greater,
+        // deeper siblings are smaller than shallower ones so they go on top
+        let mut node = &self.root;
+        let mut key = String::new();
+        for char in start.chars() {
+            for (&sibling, child) in node.children.range((Bound::Excluded(char), Bound::Unbounded)).rev() {
+                ordered.stack.push((format!("{}{}", key, sibling), child));
+            }
+            key.push(char);
+            match node.children.get(&char) {
+                Some(child) => node = child,
+                None => return ordered,
+            }
+        }
+
+        if inclusive {
+            ordered.stack.push((key, node));
+        } else {
+            ordered.push_children(&key, node);
+        }
+        ordered
+    }
+
+    // The smallest key strictly after `key`
+    pub fn successor(&self, key: &str) -> Option<(String, &[DocId])> {
+        self.range(Bound::Excluded(key), Bound::Unbounded).next()
+    }
+
+    // The largest key strictly before `key`
+    pub fn predecessor(&self, key: &str) -> Option<(String, &[DocId])> {
+        let mut node = &self.root;
+        let mut path = String::new();
+        let mut best = None;
+
+        // Going down the path, each level's candidates beat the ones above:
+        // the largest key under a smaller sibling, else the node itself
+        for char in self.normalization.apply(key).chars() {
+            if let Some((&sibling, child)) = node.children.range(..char).next_back() {
+                best = Some(Self::last_below(format!("{}{}", path, sibling), child));
+            } else if let Some(value) = &node.value {
+                best = Some((path.clone(), value.as_slice()));
+            }
+            path.push(char);
+            match node.children.get(&char) {
+                Some(child) => node = child,
+                None => break,
+            }
+        }
+        best
+    }
+
+    // The largest key in a subtree: always follow the last child
+    fn last_below(mut key: String, mut node: &TrieNode) -> (String, &[DocId]) {
+        while let Some((&char, child)) = node.children.iter().next_back() {
+            key.push(char);
+            node = child;
+        }
+        (key, node.value.as_deref().unwrap_or_default())
+    }
+
+    // Stored keys that `input` starts with, shortest first, e.g. "in" and
+    // "inter" for "internet". The inverse of a prefix search
+    pub fn prefixes_of(&self, input: &str) -> Vec<(String, &[DocId])> {
+        let mut node = &self.root;
+        let mut path = String::new();
+        let mut found = Vec::new();
+
+        for char in self.normalization.apply(input).chars() {
+            match node.children.get(&char) {
+                Some(child) => node = child,
+                None => break,
+            }
+            path.push(char);
+            if let Some(value) = &node.value {
+                found.push((path.clone(), value.as_slice()));
+            }
+        }
+        found
+    }
+
+    // The longest stored key that `input` starts with
+    pub fn longest_prefix_of(&self, input: &str) -> Option<(String, &[DocId])> {
+        self.prefixes_of(input).pop()
+    }
+
+    // Keys matching a `?`/`*` pattern in sorted order. A literal start
+    // prunes the walk to that prefix, a leading `*` visits every node
+    pub fn wildcard_search_iter(&self, pattern: &str) -> impl Iterator<Item = DocId> + '_ {
+        let pattern = Pattern::parse(&self.normalization.apply(pattern));
+        let states = pattern.start();
+        WildcardIter {
+            pattern,
+            stack: vec![(&self.root, states)],
+            ids: Default::default(),
+        }
+    }
+
+    // Every key with a prefix within `max_typos` edits of `query`, an
+    // adjacent transposition ("serach") counting as a single edit
+    pub fn fuzzy_search(&self, query: String, max_typos: usize) -> Result<Vec<DocId>, SearchError> {
+        let results: Vec<DocId> = self.fuzzy_search_iter(&query, max_typos).collect();
+
+        if results.is_empty() {
+            Err(SearchError::NoMatch(query))
+        } else {
+            Ok(results)
+        }
+    }
+
+    pub fn fuzzy_search_iter(&self, query: &str, max_typos: usize) -> impl Iterator<Item = DocId> + '_ {
+        let query: Vec<char> = self.normalization.apply(query).chars().collect();
+
+        // Row 0 of the Levenshtein automaton, i.e. the distance of each query prefix to ""
+        let row: Vec<usize> = (0..=query.len()).collect();
+        let mut fuzzy = FuzzyIter {
+            query,
+            max_typos,
+            stack: Vec::new(),
+            matches: Descendants::default(),
+        };
+        if row[fuzzy.query.len()] <= max_typos {
+            fuzzy.matches.queue.push_back(&self.root);
+        } else {
+            fuzzy.stack.push(FuzzyState { node: &self.root, row, previous: None });
+        }
+        fuzzy
+    }
+}
+
+// Breadth-first walk over the ids stored in the queued subtrees, so keys
+// come out shortest first
+#[derive(Default)]
+struct Descendants<'a> {
+    queue: VecDeque<&'a TrieNode>,
+    ids: std::slice::Iter<'a, DocId>,
+}
+
+impl Iterator for Descendants<'_> {
+    type Item = DocId;
+
+    fn next(&mut self) -> Option<DocId> {
+        loop {
+            if let Some(&id) = self.ids.next() {
+                return Some(id);
+            }
+            let node = self.queue.pop_front()?;
+            self.queue.extend(node.children.values());
             if let Some(value) = &node.value {
-                for word in value {
-                    // Only add words that start with prefix but aren't equal to it
-                    if word.starts_with(prefix) && word != prefix {
-                        results.push(word.to_string());
-                    }
+                self.ids = value.iter();
+            }
+        }
+    }
+}
+
+// Pre-order walk from a stack of (key, node) still to visit, smallest on top
+struct Ordered<'a> {
+    stack: Vec<(String, &'a TrieNode)>,
+    end: Bound<String>,
+}
+
+impl<'a> Ordered<'a> {
+    fn push_children(&mut self, key: &str, node: &'a TrieNode) {
+        for (&char, child) in node.children.iter().rev() {
+            self.stack.push((format!("{}{}", key, char), child));
+        }
+    }
+}
+
+impl<'a> Iterator for Ordered<'a> {
+    type Item = (String, &'a [DocId]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((key, node)) = self.stack.pop() {
+            let past_end = match &self.end {
+                Bound::Included(end) => key.as_str() > end.as_str(),
+                Bound::Excluded(end) => key.as_str() >= end.as_str(),
+                Bound::Unbounded => false,
+            };
+            if past_end {
+                // Everything left on the stack sorts after this key
+                self.stack.clear();
+                return None;
+            }
+
+            self.push_children(&key, node);
+            if let Some(value) = &node.value {
+                return Some((key, value.as_slice()));
+            }
+        }
+        None
+    }
+}
+
+// Depth-first walk of the pattern NFA along the trie, in sorted key order;
+// a branch is dropped as soon as no pattern state survives its edge
+struct WildcardIter<'a> {
+    pattern: Pattern,
+    stack: Vec<(&'a TrieNode, Vec<usize>)>,
+    ids: std::slice::Iter<'a, DocId>,
+}
+
+impl<'a> Iterator for WildcardIter<'a> {
+    type Item = DocId;
+
+    fn next(&mut self) -> Option<DocId> {
+        loop {
+            if let Some(&id) = self.ids.next() {
+                return Some(id);
+            }
+            let (node, states) = self.stack.pop()?;
+            for (&char, child) in node.children.iter().rev() {
+                let next = self.pattern.step(&states, char);
+                if !next.is_empty() {
+                    self.stack.push((child, next));
+                }
+            }
+            if let Some(value) = node.value.as_ref().filter(|_| self.pattern.accepts(&states)) {
+                self.ids = value.iter();
+            }
+        }
+    }
+}
+
+// Automaton state while walking the trie: the node reached, its row, plus
+// the row and character one level up, which transpositions need
+struct FuzzyState<'a> {
+    node: &'a TrieNode,
+    row: Vec<usize>,
+    previous: Option<(Vec<usize>, char)>,
+}
+
+// Depth-first walk of the automaton; subtrees whose path already matches
+// the whole query are handed to `matches` and drained before going deeper
+struct FuzzyIter<'a> {
+    query: Vec<char>,
+    max_typos: usize,
+    stack: Vec<FuzzyState<'a>>,
+    matches: Descendants<'a>,
+}
+
+impl<'a> Iterator for FuzzyIter<'a> {
+    type Item = DocId;
+
+    fn next(&mut self) -> Option<DocId> {
+        loop {
+            if let Some(id) = self.matches.next() {
+                return Some(id);
+            }
+            let state = self.stack.pop()?;
+            let query = &self.query;
+
+            for (&char, child) in state.node.children.iter().rev() {
+                let row = &state.row;
+                let next = step(query, row, state.previous.as_ref(), char);
+
+                if next[query.len()] <= self.max_typos {
+                    // The whole query matched this path, so every key below it is a hit
+                    self.matches.queue.push_back(child);
+                } else if next.iter().min().is_some_and(|&min| min <= self.max_typos) {
+                    let previous = Some((row.clone(), char));
+                    self.stack.push(FuzzyState { node: child, row: next, previous });
                 }
             }
         }
-        
-        for child in node.children.iter().flatten() {
-            self.collect_words(child, prefix, results);
+    }
+}
+
+// Steps the automaton over one more character of the path: the distance of
+// each query prefix to the path so far, given the row before and, for
+// transpositions, the row and character one level further up
+fn step(query: &[char], row: &[usize], previous: Option<&(Vec<usize>, char)>, char: char) -> Vec<usize> {
+    let mut next = Vec::with_capacity(row.len());
+    next.push(row[0] + 1);
+    for index in 1..row.len() {
+        let substitution = row[index - 1] + usize::from(query[index - 1] != char);
+        let mut distance = substitution.min(row[index] + 1).min(next[index - 1] + 1);
+        if let Some((previous_row, previous_char)) = previous {
+            if index > 1 && query[index - 1] == *previous_char && query[index - 2] == char {
+                distance = distance.min(previous_row[index - 2] + 1);
+            }
         }
+        next.push(distance);
+    }
+    next
+}
+
+// Fewest edits between `query` and any prefix of `key`, both already
+// folded: what `fuzzy_search` compares against `max_typos`
+pub fn prefix_distance(query: &str, key: &str) -> usize {
+    let query: Vec<char> = query.chars().collect();
+    let mut row: Vec<usize> = (0..=query.len()).collect();
+    let mut previous = None;
+    let mut best = row[query.len()];
+    for char in key.chars() {
+        let next = step(&query, &row, previous.as_ref(), char);
+        best = best.min(next[query.len()]);
+        previous = Some((std::mem::replace(&mut row, next), char));
+    }
+    best
+}
+
+impl Searchable for Trie {
+    fn name(&self) -> &'static str {
+        "trie"
+    }
+
+    fn normalization(&self) -> Normalization {
+        self.normalization
+    }
+
+    fn search<'a>(&'a self, query: &Query, _strings: &'a StringTable) -> Option<Matches<'a>> {
+        match *query {
+            Query::Prefix(prefix) => Some(Box::new(self.search_iter(prefix))),
+            Query::Fuzzy { term, max_typos } => Some(Box::new(self.fuzzy_search_iter(term, max_typos))),
+            Query::Wildcard(pattern) => Some(Box::new(self.wildcard_search_iter(pattern))),
+            Query::Range { start, end } => {
+                Some(Box::new(self.range(start, end).flat_map(|(_, ids)| ids.iter().copied())))
+            }
+            Query::Successor(key) => Some(Box::new(self.successor(key).into_iter().flat_map(|(_, ids)| ids.iter().copied()))),
+            Query::Predecessor(key) => Some(Box::new(self.predecessor(key).into_iter().flat_map(|(_, ids)| ids.iter().copied()))),
+            _ => None,
+        }
+    }
+
+    fn shortest_first(&self, query: &Query) -> bool {
+        matches!(query, Query::Prefix(_))
+    }
+
+    fn top_k(&self, prefix: &str, k: usize) -> Option<Vec<(DocId, u32)>> {
+        Some(Trie::top_k(self, prefix, k))
+    }
+
+    fn prefixes_of<'a>(&'a self, input: &str) -> Option<Vec<(String, &'a [DocId])>> {
+        Some(Trie::prefixes_of(self, input))
+    }
+
+    fn len(&self) -> usize {
+        self.stats().postings
+    }
+
+    fn stats(&self) -> Stats {
+        let mut stats = Stats::default();
+        self.root.tally(&mut stats);
+        stats
+    }
+}
+
+impl SearchStructure for Trie {
+    fn store(&mut self, key: String, strings: &mut StringTable) {
+        Trie::store(self, key, strings)
+    }
+
+    fn remove(&mut self, key: String, strings: &StringTable) -> bool {
+        Trie::remove(self, key, strings)
     }
 }
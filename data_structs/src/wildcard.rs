@@ -0,0 +1,117 @@
+// Glob-style patterns over whole keys: `?` matches exactly one char and
+// `*` any run of chars, including none. Matching runs as an NFA whose
+// states are positions in the pattern, so the trees can step it one edge
+// at a time and drop a branch as soon as no state survives
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    tokens: Vec<Token>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    Char(char),
+    One,
+    Many,
+}
+
+impl Pattern {
+    pub fn parse(pattern: &str) -> Self {
+        let mut tokens: Vec<Token> = Vec::new();
+        for char in pattern.chars() {
+            let token = match char {
+                '?' => Token::One,
+                '*' => Token::Many,
+                char => Token::Char(char),
+            };
+            // `**` means the same as `*` and only costs extra states
+            if token == Token::Many && tokens.last() == Some(&Token::Many) {
+                continue;
+            }
+            tokens.push(token);
+        }
+        Self { tokens }
+    }
+
+    // The same pattern read right to left, for walking reversed keys
+    pub fn reversed(&self) -> Self {
+        Self {
+            tokens: self.tokens.iter().rev().copied().collect(),
+        }
+    }
+
+    // Chars fixed at the start of every match
+    pub fn literal_prefix_len(&self) -> usize {
+        self.tokens.iter().take_while(|token| matches!(token, Token::Char(_))).count()
+    }
+
+    // Chars fixed at the end of every match
+    pub fn literal_suffix_len(&self) -> usize {
+        self.tokens.iter().rev().take_while(|token| matches!(token, Token::Char(_))).count()
+    }
+
+    // The runs of plain chars between wildcards, e.g. ["ment", "al"] for `*ment*al`
+    pub fn literals(&self) -> Vec<String> {
+        let mut literals = Vec::new();
+        let mut current = String::new();
+        for token in self.tokens.iter() {
+            match token {
+                Token::Char(char) => current.push(*char),
+                _ if !current.is_empty() => literals.push(std::mem::take(&mut current)),
+                _ => {}
+            }
+        }
+        if !current.is_empty() {
+            literals.push(current);
+        }
+        literals
+    }
+
+    // States before reading anything
+    pub fn start(&self) -> Vec<usize> {
+        self.closure(vec![0])
+    }
+
+    // States after reading `char`; empty once the pattern can no longer match
+    pub fn step(&self, states: &[usize], char: char) -> Vec<usize> {
+        let mut next = Vec::with_capacity(states.len() + 1);
+        for &state in states.iter() {
+            match self.tokens.get(state) {
+                Some(Token::Char(expected)) if *expected == char => next.push(state + 1),
+                Some(Token::One) => next.push(state + 1),
+                Some(Token::Many) => next.push(state),
+                _ => {}
+            }
+        }
+        self.closure(next)
+    }
+
+    pub fn accepts(&self, states: &[usize]) -> bool {
+        states.contains(&self.tokens.len())
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        let mut states = self.start();
+        for char in text.chars() {
+            states = self.step(&states, char);
+            if states.is_empty() {
+                return false;
+            }
+        }
+        self.accepts(&states)
+    }
+
+    // A `*` may match nothing, so reaching it also reaches the next position
+    fn closure(&self, mut states: Vec<usize>) -> Vec<usize> {
+        let mut index = 0;
+        while index < states.len() {
+            let state = states[index];
+            if self.tokens.get(state) == Some(&Token::Many) {
+                states.push(state + 1);
+            }
+            index += 1;
+        }
+        states.sort_unstable();
+        states.dedup();
+        states
+    }
+}
@@ -1,13 +1,18 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use std::sync::mpsc::channel;
-use runtime::{perform_search, Scope, SearchType, load_index}; // Replace `your_crate_name` with the actual crate name
+use runtime::{perform_search, ContainsMode, Scope, SearchType, load_index}; // Replace `your_crate_name` with the actual crate name
 
 
 fn benchmark_perform_search(c: &mut Criterion) {
     let index = load_index().expect("Failed to load index");
     let term = "me";
     let scopes = [Scope::Words, Scope::Lines];
-    let search_types = [SearchType::Prefix, SearchType::Suffix, SearchType::Contains];
+    let search_types = [
+        SearchType::Prefix,
+        SearchType::Suffix,
+        SearchType::Contains(ContainsMode::StrictInfix),
+        SearchType::Contains(ContainsMode::Anywhere),
+    ];
 
     for scope in &scopes {
         for search_type in &search_types {
@@ -25,7 +30,7 @@ fn benchmark_perform_search(c: &mut Criterion) {
                 term,
                 sender.clone(),
             )
-            .len();
+            .map_or(0, |results| results.len());
 
             // Print once before the benchmark
             println!("Result length for {:?}_{:?}: {}", scope, search_type, result_len);
@@ -33,7 +38,7 @@ fn benchmark_perform_search(c: &mut Criterion) {
             c.bench_function(&bench_name, move |b| {
                 b.iter(|| {
                     let (sender_inner, _receiver_inner) = channel();
-                    perform_search(
+                    let _ = perform_search(
                         black_box(&index_clone),
                         black_box(scope_clone.clone()),
                         black_box(search_type_clone.clone()),
@@ -0,0 +1,89 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use runtime::{
+    load_index_with, longest_common_substring, longest_repeated_substring, most_frequent_substrings, LoadOptions, RuntimeError, Scope,
+};
+
+const USAGE: &str = "usage:
+  analytics [--index-dir DIR] repeated <words|lines>
+  analytics [--index-dir DIR] frequent <words|lines> <length> [count]
+  analytics common <file-or-text> <file-or-text>
+
+DIR is where compile wrote its indexes [default: serialized_outputs]";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => {
+            eprintln!("{}", USAGE);
+            ExitCode::FAILURE
+        }
+        Err(e) => {
+            eprintln!("Analysis failed - {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// `Ok(false)` when the arguments do not form a command
+fn run(args: &[String]) -> Result<bool, RuntimeError> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let (index_dir, args) = match args.as_slice() {
+        ["--index-dir", index_dir, rest @ ..] => (PathBuf::from(index_dir), rest),
+        rest => (PathBuf::from("serialized_outputs"), rest),
+    };
+    let options = LoadOptions {
+        index_dir: Some(index_dir),
+        ..LoadOptions::default()
+    };
+    match args {
+        ["repeated", scope] => {
+            let Some(scope) = parse_scope(scope) else {
+                return Ok(false);
+            };
+            let index = load_index_with(&options)?;
+            match longest_repeated_substring(&index, &scope)? {
+                Some((substring, count)) => println!("{:?} occurs {} times", substring, count),
+                None => println!("nothing occurs twice"),
+            }
+        }
+        ["frequent", scope, length, rest @ ..] if rest.len() <= 1 => {
+            let (Some(scope), Ok(length)) = (parse_scope(scope), length.parse::<usize>()) else {
+                return Ok(false);
+            };
+            let Ok(count) = rest.first().map_or(Ok(10), |count| count.parse::<usize>()) else {
+                return Ok(false);
+            };
+            let index = load_index_with(&options)?;
+            for (substring, occurrences) in most_frequent_substrings(&index, &scope, length, count)? {
+                println!("{:>8}  {:?}", occurrences, substring);
+            }
+        }
+        ["common", first, second] => {
+            let (first, second) = (file_or_text(first)?, file_or_text(second)?);
+            println!("{:?}", longest_common_substring(&first, &second));
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+fn parse_scope(scope: &str) -> Option<Scope> {
+    match scope {
+        "words" => Some(Scope::Words),
+        "lines" => Some(Scope::Lines),
+        _ => None,
+    }
+}
+
+// An argument naming an existing file stands for its contents
+fn file_or_text(argument: &str) -> Result<String, RuntimeError> {
+    let path = Path::new(argument);
+    if !path.is_file() {
+        return Ok(argument.to_string());
+    }
+    fs::read_to_string(path).map_err(|source| RuntimeError::Io { path: path.to_path_buf(), source })
+}
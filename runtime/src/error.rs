@@ -0,0 +1,41 @@
+use std::io;
+use std::path::PathBuf;
+use data_structs::error::SearchError;
+use thiserror::Error;
+
+// Why loading or querying the indexes failed. An empty result is not an
+// error, it comes back as `Ok` with no rows
+#[derive(Debug, Error)]
+pub enum RuntimeError {
+    #[error("could not determine the project root")]
+    ProjectRoot,
+    #[error("failed to read {path:?}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    // Covers corrupt files as well as files written by an older format version
+    #[error("failed to load {path:?}: {source}")]
+    Load {
+        path: PathBuf,
+        #[source]
+        source: SearchError,
+    },
+    // A partial rebuild rewrote the string table but left this index behind
+    #[error("{path:?} was built against a different string table than {strings:?}, rebuild the scope")]
+    StaleIndex {
+        path: PathBuf,
+        strings: PathBuf,
+    },
+    #[error("no index loaded for {0}")]
+    IndexMissing(String),
+    #[error("indexes of the {scope} scope were built with different normalizations ({first} and {other}), rebuild them")]
+    MixedNormalization {
+        scope: &'static str,
+        first: &'static str,
+        other: &'static str,
+    },
+    #[error(transparent)]
+    Search(#[from] SearchError),
+}
@@ -1,17 +1,34 @@
-use bincode::config;
-use std::collections::HashMap;
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::sync::mpsc::Sender;
 use unicode_segmentation::UnicodeSegmentation;
 
 use levenshtein::levenshtein;
+use regex::Regex;
 
+use data_structs::error::SearchError;
+use data_structs::normalize::Normalization;
+use data_structs::prefilter::required_literals;
+use data_structs::strings::{DocId, StringTable};
+use data_structs::structure::{decode_versioned, Matches, Phrases, Query, SearchStructure, Searchable, Substrings};
+pub use data_structs::structure::{ContainsMode, Similarity};
 use data_structs::trees;
+use data_structs::wildcard::Pattern;
 
+use trees::fst::FstIndex;
 use trees::ngram::NGramIndex;
+use trees::positional::PositionalIndex;
+use trees::radix::RadixTree;
 use trees::suffix::SuffixTree;
-use trees::trie::Trie;
+use trees::suffix_array::SuffixArray;
+use trees::fm_index::FmIndex;
+use trees::trie::{prefix_distance, Trie};
+
+mod error;
+pub use error::RuntimeError;
 
 #[derive(Debug, Clone)]
 pub enum Scope {
@@ -19,225 +36,673 @@ pub enum Scope {
     Lines,
 }
 
+// Loaded indexes are shared, so handing the map to a search thread is cheap
 #[derive(Clone)]
 pub enum SearchIndex {
-    Trie(Trie),
-    SuffixTree(SuffixTree),
-    NGramIndex(NGramIndex),
+    Structure(Arc<dyn Searchable>),
+    Strings(Arc<StringTable>),
+}
+
+impl SearchIndex {
+    // The case policy the index was built with, `None` for the string table
+    pub fn normalization(&self) -> Option<Normalization> {
+        match self {
+            SearchIndex::Structure(structure) => Some(structure.normalization()),
+            SearchIndex::Strings(_) => None,
+        }
+    }
+}
+
+// Index kinds in the order a query tries them; the first that supports the
+// query answers it, so the radix tree wins prefix queries over the trie, the
+// suffix array or FM-index (only one is loaded) wins contains queries over
+// the n-gram index, and the FST only answers when the trees were not deployed
+const PREFERENCE: [&str; 8] = ["RadixTree", "Trie", "SuffixTree", "SuffixArray", "FmIndex", "NGramIndex", "Positional", "Fst"];
+
+// The bincode-serialized index kinds and the file each one is read from.
+// Loading also yields the checksum of the string table it was built against
+type Loader = fn(&[u8]) -> Result<(Arc<dyn Searchable>, u64), SearchError>;
+
+const STRUCTURES: [(&str, &str, Loader); 7] = [
+    ("Trie", "trie-serial.bin", load_structure::<Trie>),
+    ("RadixTree", "radix-serial.bin", load_structure::<RadixTree>),
+    ("SuffixTree", "suffix-serial.bin", load_structure::<SuffixTree>),
+    ("SuffixArray", "suffix-array-serial.bin", load_structure::<SuffixArray>),
+    ("FmIndex", "fm-index-serial.bin", load_structure::<FmIndex>),
+    ("NGramIndex", "ngram-serial.bin", load_structure::<NGramIndex>),
+    ("Positional", "positional-serial.bin", load_structure::<PositionalIndex>),
+];
+
+fn load_structure<T: SearchStructure + 'static>(bytes: &[u8]) -> Result<(Arc<dyn Searchable>, u64), SearchError> {
+    let (structure, table) = T::decode_from_slice(bytes)?;
+    Ok((Arc::new(structure), table))
+}
+
+fn scope_suffix(scope: &Scope) -> &'static str {
+    match scope {
+        Scope::Words => "Word",
+        Scope::Lines => "Line",
+    }
+}
+
+// How the scope's indexes fold text, they all agree once loaded
+pub fn normalization_for(index: &HashMap<String, SearchIndex>, scope: &Scope) -> Normalization {
+    structures_for(index, scope).map(|structure| structure.normalization()).next().unwrap_or_default()
+}
+
+// The scope's indexes in preference order
+fn structures_for<'a>(index: &'a HashMap<String, SearchIndex>, scope: &Scope) -> impl Iterator<Item = &'a dyn Searchable> {
+    structures_of(index, scope, &PREFERENCE)
+}
+
+// The scope's indexes of the given kinds, skipping those not loaded
+fn structures_of<'a>(
+    index: &'a HashMap<String, SearchIndex>,
+    scope: &Scope,
+    kinds: &'a [&'a str],
+) -> impl Iterator<Item = &'a dyn Searchable> {
+    let suffix = scope_suffix(scope);
+    kinds.iter().filter_map(move |kind| match index.get(&format!("{}_{}", kind, suffix)) {
+        Some(SearchIndex::Structure(structure)) => Some(structure.as_ref()),
+        _ => None,
+    })
 }
 
 #[derive(Debug, Clone)]
 pub enum SearchType {
     Prefix,
     Suffix,
-    Contains,
+    Contains(ContainsMode),
+    Fuzzy,
+    Autocomplete,
+    Range,
+    Successor,
+    Predecessor,
+    Wildcard,
+    Regex,
+    Phrase,
+    Similar,
 }
 
+// How many ranked results a search hands back to the UI
+pub const MAX_RESULTS: usize = 100;
+
+// How alike a string must be to count as similar, pg_trgm's default
+pub const SIMILARITY_THRESHOLD: f32 = 0.3;
+
+// How many strings a regex without usable literals is tried against
+pub const REGEX_SCAN_LIMIT: usize = 100_000;
+
 pub enum AppMessage {
     SearchComplete(Vec<(u8, String)>, std::time::Duration),
     Debug(String),
 }
 
+// Meilisearch-style typo budget: none under 5 chars, 1 up to 8, 2 beyond
+pub fn allowed_typos(term: &str) -> usize {
+    match term.chars().count() {
+        0..=4 => 0,
+        5..=8 => 1,
+        _ => 2,
+    }
+}
+
+// Range terms use Rust's syntax: `ma..me`, `ma..=me`, `kite..` or `..me`.
+// A bare word browses the words after it, itself excluded; `kite..`
+// includes it
+pub fn parse_range(term: &str) -> (Bound<&str>, Bound<&str>) {
+    let Some((start, end)) = term.split_once("..") else {
+        return (Bound::Excluded(term.trim()), Bound::Unbounded);
+    };
+    let start = match start.trim() {
+        "" => Bound::Unbounded,
+        start => Bound::Included(start),
+    };
+    let end = match end.strip_prefix('=') {
+        Some(end) => Bound::Included(end.trim()),
+        None if end.trim().is_empty() => Bound::Unbounded,
+        None => Bound::Excluded(end.trim()),
+    };
+    (start, end)
+}
+
+// Proximity terms read `quick NEAR/3 fox`: both sides at most 3 words apart.
+// Either side may be a phrase, quotes around it are dropped
+pub fn parse_near(term: &str) -> Option<(&str, &str, usize)> {
+    let (first, rest) = term.split_once("NEAR/")?;
+    let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+    let distance = rest[..digits].parse().ok()?;
+    let (first, second) = (first.trim().trim_matches('"'), rest[digits..].trim().trim_matches('"'));
+    (!first.is_empty() && !second.is_empty()).then_some((first, second, distance))
+}
+
+// Which indexes walk a wildcard pattern cheapest: a literal start prunes
+// the trie, a literal end the suffix tree, and a pattern open at both ends
+// is narrowed through the n-gram index before falling back to a full walk
+fn wildcard_plan(pattern: &str) -> &'static [&'static str] {
+    let pattern = Pattern::parse(pattern);
+    let (prefix, suffix) = (pattern.literal_prefix_len(), pattern.literal_suffix_len());
+    if prefix > 0 && prefix >= suffix {
+        &["Trie"]
+    } else if suffix > 0 {
+        &["SuffixTree"]
+    } else {
+        &["NGramIndex", "Trie"]
+    }
+}
+
+// The text every index of `scope` points into
+pub fn strings_for<'a>(index: &'a HashMap<String, SearchIndex>, scope: &Scope) -> Result<&'a StringTable, RuntimeError> {
+    let key = format!("Strings_{}", scope_suffix(scope));
+    match index.get(&key) {
+        Some(SearchIndex::Strings(strings)) => Ok(strings),
+        _ => Err(RuntimeError::IndexMissing(key)),
+    }
+}
+
+// Completions of `term` ranked by stored frequency rather than edit distance
+pub fn autocomplete(
+    index: &HashMap<String, SearchIndex>,
+    scope: &Scope,
+    term: &str,
+    k: usize,
+) -> Result<Vec<(u32, String)>, RuntimeError> {
+    let strings = strings_for(index, scope)?;
+
+    let completions = structures_for(index, scope)
+        .find_map(|structure| structure.top_k(term, k))
+        .ok_or_else(|| RuntimeError::IndexMissing(format!("autocomplete in the {} scope", scope_suffix(scope))))?;
+    Ok(completions
+        .into_iter()
+        .filter_map(|(id, weight)| Some((weight, strings.resolve(id)?.to_string())))
+        .collect())
+}
+
+// Stored keys that `input` starts with, shortest first, each with the
+// strings stored under it. Keys come back folded like the index that
+// answered, e.g. for dictionary tokenization or routing tables
+pub fn prefixes_of(
+    index: &HashMap<String, SearchIndex>,
+    scope: &Scope,
+    input: &str,
+) -> Result<Vec<(String, Vec<String>)>, RuntimeError> {
+    let strings = strings_for(index, scope)?;
+
+    let prefixes = structures_for(index, scope)
+        .find_map(|structure| structure.prefixes_of(input))
+        .ok_or_else(|| RuntimeError::IndexMissing(format!("prefix lookups in the {} scope", scope_suffix(scope))))?;
+    Ok(prefixes
+        .into_iter()
+        .map(|(key, ids)| {
+            let mut seen = HashSet::new();
+            let stored = ids
+                .iter()
+                .filter(|id| seen.insert(**id))
+                .filter_map(|&id| Some(strings.resolve(id)?.to_string()))
+                .collect();
+            (key, stored)
+        })
+        .collect())
+}
+
+// The longest stored key that `input` starts with
+pub fn longest_prefix_match(
+    index: &HashMap<String, SearchIndex>,
+    scope: &Scope,
+    input: &str,
+) -> Result<Option<(String, Vec<String>)>, RuntimeError> {
+    Ok(prefixes_of(index, scope, input)?.pop())
+}
+
+// Every place `term` occurs in the scope's distinct strings, as (string,
+// byte offset into its folded form)
+pub fn occurrences(
+    index: &HashMap<String, SearchIndex>,
+    scope: &Scope,
+    term: &str,
+) -> Result<Vec<(String, usize)>, RuntimeError> {
+    let strings = strings_for(index, scope)?;
+
+    let positions = structures_for(index, scope)
+        .find_map(|structure| structure.positions(term))
+        .ok_or_else(|| RuntimeError::IndexMissing(format!("occurrence lookups in the {} scope", scope_suffix(scope))))?;
+    Ok(positions
+        .into_iter()
+        .filter_map(|(id, offset)| Some((strings.resolve(id)?.to_string(), offset)))
+        .collect())
+}
+
+// How often `term` occurs in the scope, counting every stored repeat
+pub fn count_occurrences(index: &HashMap<String, SearchIndex>, scope: &Scope, term: &str) -> Result<usize, RuntimeError> {
+    structures_for(index, scope)
+        .find_map(|structure| structure.count(term))
+        .ok_or_else(|| RuntimeError::IndexMissing(format!("occurrence counts in the {} scope", scope_suffix(scope))))
+}
+
+// The repeat statistics of the scope's text, read from the index compile
+// built over the scope's tokens
+fn substrings_for<'a>(index: &'a HashMap<String, SearchIndex>, scope: &Scope) -> Result<&'a dyn Substrings, RuntimeError> {
+    structures_for(index, scope)
+        .find_map(|structure| structure.substrings())
+        .ok_or_else(|| RuntimeError::IndexMissing(format!("substring analytics in the {} scope", scope_suffix(scope))))
+}
+
+// Longest folded substring occurring at least twice in the scope, with
+// its occurrence count
+pub fn longest_repeated_substring(index: &HashMap<String, SearchIndex>, scope: &Scope) -> Result<Option<(String, usize)>, RuntimeError> {
+    Ok(substrings_for(index, scope)?.longest_repeated())
+}
+
+// The `n` most frequent folded substrings of `length` chars in the scope
+pub fn most_frequent_substrings(
+    index: &HashMap<String, SearchIndex>,
+    scope: &Scope,
+    length: usize,
+    n: usize,
+) -> Result<Vec<(String, usize)>, RuntimeError> {
+    Ok(substrings_for(index, scope)?.most_frequent(length, n))
+}
+
+// Longest substring shared by two lines or file contents, compared as given
+pub fn longest_common_substring(first: &str, second: &str) -> String {
+    trees::suffix_array::longest_common_substring(first, second)
+}
+
+// Lines holding `term` as a phrase (`quick brown fox`, quotes optional) or,
+// for `first NEAR/k second`, both sides within k words of each other. Each
+// line comes with the word positions, counted from 0, where the matched
+// runs start. Lines keep string table order
+pub fn phrase_search(
+    index: &HashMap<String, SearchIndex>,
+    scope: &Scope,
+    term: &str,
+) -> Result<Vec<(String, Vec<usize>)>, RuntimeError> {
+    let phrases = phrases_for(index, scope)?;
+    let strings = strings_for(index, scope)?;
+
+    let matches = match parse_near(term) {
+        Some((first, second, distance)) => phrases.near(first, second, distance),
+        None if term.contains("NEAR/") => {
+            return Err(SearchError::InvalidQuery("expected `first NEAR/k second`".to_string()).into());
+        }
+        None => phrases.phrase(term.trim().trim_matches('"')),
+    };
+    Ok(matches
+        .into_iter()
+        .filter_map(|(id, positions)| Some((strings.resolve(id)?.to_string(), positions)))
+        .collect())
+}
+
+fn phrases_for<'a>(index: &'a HashMap<String, SearchIndex>, scope: &Scope) -> Result<&'a dyn Phrases, RuntimeError> {
+    structures_for(index, scope)
+        .find_map(|structure| structure.phrases())
+        .ok_or_else(|| RuntimeError::IndexMissing(format!("phrase queries in the {} scope", scope_suffix(scope))))
+}
+
+// Stored strings whose n-gram sets are at least `threshold` alike to
+// `term`'s, best first, each with its score. Only strings sharing grams
+// with the term are looked at, so long strings stay cheap where ranking
+// every candidate by edit distance is not
+pub fn similar_search(
+    index: &HashMap<String, SearchIndex>,
+    scope: &Scope,
+    term: &str,
+    similarity: Similarity,
+    threshold: f32,
+) -> Result<Vec<(String, f32)>, RuntimeError> {
+    if !(0.0..=1.0).contains(&threshold) {
+        return Err(SearchError::InvalidQuery(format!("the similarity threshold {} is outside 0..=1", threshold)).into());
+    }
+    let strings = strings_for(index, scope)?;
+
+    let matches = structures_for(index, scope)
+        .find_map(|structure| structure.similar(term, similarity, threshold, strings))
+        .ok_or_else(|| RuntimeError::IndexMissing(format!("similarity search in the {} scope", scope_suffix(scope))))?;
+    Ok(matches
+        .into_iter()
+        .filter_map(|(id, score)| Some((strings.resolve(id)?.to_string(), score)))
+        .collect())
+}
+
+// Regexes run against the original text. The suffix array, FM-index or
+// word n-grams narrow them to the strings holding their literal fragments;
+// without any, only the first REGEX_SCAN_LIMIT strings are scanned.
+// Matches keep string table order
+pub fn regex_search(
+    index: &HashMap<String, SearchIndex>,
+    scope: &Scope,
+    pattern: &str,
+    debug_sender: &Sender<AppMessage>,
+) -> Result<Vec<(u8, String)>, RuntimeError> {
+    if pattern.trim().is_empty() {
+        return Err(SearchError::InvalidQuery("the regex is empty".to_string()).into());
+    }
+    let regex = Regex::new(pattern).map_err(|e| SearchError::InvalidQuery(e.to_string()))?;
+    let strings = strings_for(index, scope)?;
+    let query = Query::Regex(&regex);
+
+    let (message, candidates): (String, Matches) = match structures_for(index, scope)
+        .find_map(|structure| Some((structure, structure.search(&query, strings)?)))
+    {
+        Some((structure, candidates)) => (
+            format!("Searching {} scope with the {} index", scope_suffix(scope), structure.name()),
+            candidates,
+        ),
+        None => {
+            let limit = strings.len().min(REGEX_SCAN_LIMIT);
+            let scan = (0..limit as DocId).filter(|&id| strings.resolve(id).is_some_and(|item| regex.is_match(item)));
+            let reason = if required_literals(pattern).is_empty() {
+                "regex has no literal the index can use".to_string()
+            } else {
+                format!("no loaded index narrows regexes in the {} scope", scope_suffix(scope))
+            };
+            (
+                format!("Warning: {}, scanning {} of {} strings", reason, limit, strings.len()),
+                Box::new(scan),
+            )
+        }
+    };
+    if let Err(e) = debug_sender.send(AppMessage::Debug(message)) {
+        eprintln!("Failed to send debug message: {}", e);
+    }
+
+    Ok(candidates
+        .filter_map(|id| strings.resolve(id))
+        .take(MAX_RESULTS)
+        .enumerate()
+        .map(|(rank, item)| (rank as u8, item.to_string()))
+        .collect())
+}
+
 pub fn perform_search(
     index: &HashMap<String, SearchIndex>,
     scope: Scope,
     search_type: SearchType,
     term: &str,
     debug_sender: Sender<AppMessage>,
-) -> Vec<(u8, String)> {
-    let mut sorted_result: Vec<(u8, String)> = Vec::new();
-    let scope_path = match scope {
-        Scope::Words => "word_scope",
-        Scope::Lines => "line_scope",
+) -> Result<Vec<(u8, String)>, RuntimeError> {
+    let query = match search_type {
+        SearchType::Prefix => Query::Prefix(term),
+        SearchType::Suffix => Query::Suffix(term),
+        SearchType::Contains(mode) => Query::Contains(term, mode),
+        SearchType::Fuzzy => Query::Fuzzy { term, max_typos: allowed_typos(term) },
+        SearchType::Range => {
+            let (start, end) = parse_range(term);
+            Query::Range { start, end }
+        }
+        SearchType::Successor => Query::Successor(term),
+        SearchType::Predecessor => Query::Predecessor(term),
+        SearchType::Wildcard => Query::Wildcard(term),
+        SearchType::Regex => return regex_search(index, &scope, term, &debug_sender),
+        // Matches are listed in line order with their positions appended
+        SearchType::Phrase => {
+            if term.trim().is_empty() {
+                return Err(SearchError::InvalidQuery("the phrase is empty".to_string()).into());
+            }
+            let matches = phrase_search(index, &scope, term)?;
+            let message = format!("Searching {} scope with the positional index, {} lines", scope_suffix(&scope), matches.len());
+            if let Err(e) = debug_sender.send(AppMessage::Debug(message)) {
+                eprintln!("Failed to send debug message: {}", e);
+            }
+            return Ok(matches
+                .into_iter()
+                .take(MAX_RESULTS)
+                .enumerate()
+                .map(|(rank, (line, positions))| {
+                    let positions: Vec<String> = positions.iter().map(usize::to_string).collect();
+                    (rank as u8, format!("{}  (words {})", line, positions.join(", ")))
+                })
+                .collect());
+        }
+        // Ranked by score, with the score appended
+        SearchType::Similar => {
+            if term.trim().is_empty() {
+                return Err(SearchError::InvalidQuery("the search term is empty".to_string()).into());
+            }
+            let similarity = Similarity::default();
+            let matches = similar_search(index, &scope, term, similarity, SIMILARITY_THRESHOLD)?;
+            let message = format!(
+                "Searching {} scope with the ngram index, {:?} similarity of at least {}, {} matches",
+                scope_suffix(&scope),
+                similarity,
+                SIMILARITY_THRESHOLD,
+                matches.len()
+            );
+            if let Err(e) = debug_sender.send(AppMessage::Debug(message)) {
+                eprintln!("Failed to send debug message: {}", e);
+            }
+            return Ok(matches
+                .into_iter()
+                .take(MAX_RESULTS)
+                .enumerate()
+                .map(|(rank, (item, score))| (rank as u8, format!("{}  ({:.2})", item, score)))
+                .collect());
+        }
+        // Completions come back already ranked by frequency, keep that order
+        SearchType::Autocomplete => {
+            return Ok(autocomplete(index, &scope, term, MAX_RESULTS)?
+                .into_iter()
+                .enumerate()
+                .map(|(rank, (_, word))| (rank as u8, word))
+                .collect());
+        }
     };
 
-    let type_path = match search_type {
-        SearchType::Prefix => "trie-serial.bin",
-        SearchType::Suffix => "suffix-serial.bin",
-        SearchType::Contains => "ngram-serial.bin",
-    };
+    if term.trim().is_empty() {
+        return Err(SearchError::InvalidQuery("the search term is empty".to_string()).into());
+    }
+    let strings = strings_for(index, &scope)?;
 
-    let path = format!("./serialized_outputs/{}/{}", scope_path, type_path);
-    //let file_path = Path::new(&path);
+    let planned = match search_type {
+        SearchType::Wildcard => wildcard_plan(term),
+        _ => &[],
+    };
+    let Some((structure, candidates)) = structures_of(index, &scope, planned)
+        .chain(structures_for(index, &scope))
+        .find_map(|structure| Some((structure, structure.search(&query, strings)?)))
+    else {
+        return Err(RuntimeError::IndexMissing(format!("{:?} search in the {} scope", search_type, scope_suffix(&scope))));
+    };
 
-    if let Err(e) = debug_sender.send(AppMessage::Debug(format!("Searching in file: {}", path))) {
+    let mut message = format!("Searching {} scope with the {} index", scope_suffix(&scope), structure.name());
+    if let SearchType::Contains(mode) = search_type {
+        message.push_str(&format!(", {} matches", mode.name()));
+        if let Some(count) = structure.count(term) {
+            message.push_str(&format!(", {} occurrences", count));
+        }
+    }
+    if let Err(e) = debug_sender.send(AppMessage::Debug(message)) {
         eprintln!("Failed to send debug message: {}", e);
     }
 
-    let message = match search_type {
-        SearchType::Prefix => "TRIE decoded successfully".to_string(),
-        SearchType::Suffix => "SUFFIX decoded successfully".to_string(),
-        SearchType::Contains => "NGRAM decoded successfully".to_string(),
-    };
+    // Structures yield a string once per time it was stored; every path
+    // below lists it once
+    let mut seen = HashSet::new();
+    let candidates = candidates.filter(move |id| seen.insert(*id));
 
-    let results = match search_type {
-        SearchType::Contains => match scope {
-            Scope::Words => index.get("NGramIndex_Word").and_then(|idx| {
-                if let SearchIndex::NGramIndex(ngram_index) = idx {
-                    ngram_index
-                        .search(term.to_string())
-                        .ok()
-                        .map(Some)
-                        .unwrap_or(None)
-                } else {
-                    None
-                }
-            }),
-            Scope::Lines => index.get("NGramIndex_Line").and_then(|idx| {
-                if let SearchIndex::NGramIndex(ngram_index) = idx {
-                    ngram_index
-                        .search(term.to_string())
-                        .ok()
-                        .map(Some)
-                        .unwrap_or(None)
-                } else {
-                    None
-                }
-            }),
-        },
-        SearchType::Suffix => match scope {
-            Scope::Words => index.get("SuffixTree_Word").and_then(|idx| {
-                if let SearchIndex::SuffixTree(suffix_tree) = idx {
-                    suffix_tree
-                        .search(term.to_string())
-                        .ok()
-                        .map(Some)
-                        .unwrap_or(None)
-                } else {
-                    None
-                }
-            }),
-            Scope::Lines => index.get("SuffixTree_Line").and_then(|idx| {
-                if let SearchIndex::SuffixTree(suffix_tree) = idx {
-                    suffix_tree
-                        .search(term.to_string())
-                        .ok()
-                        .map(Some)
-                        .unwrap_or(None)
-                } else {
-                    None
-                }
-            }),
-        },
-        SearchType::Prefix => match scope {
-            Scope::Words => index.get("Trie_Word").and_then(|idx| {
-                if let SearchIndex::Trie(trie) = idx {
-                    trie.search(term.to_string()).ok().map(Some).unwrap_or(None)
-                } else {
-                    None
-                }
-            }),
-            Scope::Lines => index.get("Trie_Line").and_then(|idx| {
-                if let SearchIndex::Trie(trie) = idx {
-                    trie.search(term.to_string()).ok().map(Some).unwrap_or(None)
-                } else {
-                    None
-                }
-            }),
-        },
-    };
+    // Ranges and neighbours are for browsing, keep them in sorted order
+    // instead of ranking
+    if let SearchType::Range | SearchType::Successor | SearchType::Predecessor = search_type {
+        return Ok(candidates
+            .filter_map(|id| strings.resolve(id))
+            .take(MAX_RESULTS)
+            .enumerate()
+            .map(|(rank, item)| (rank as u8, item.to_string()))
+            .collect());
+    }
 
-    if let Some(results) = results {
-        if let Err(e) = debug_sender.send(AppMessage::Debug("File read successfully".to_string())) {
-            eprintln!("Failed to send debug message: {}", e);
-        }
-        if let Err(e) = debug_sender.send(AppMessage::Debug(message)) {
-            eprintln!("Failed to send debug message: {}", e);
-        }
-
-        for item in results.iter() {
-            if matches!(scope, Scope::Lines) {
-                let lines_scope = item.unicode_words().collect::<Vec<&str>>();
-                if let (Some(first_word), Some(last_word)) =
-                    (lines_scope.first(), lines_scope.last())
-                {
-                    let condition = match search_type {
-                        SearchType::Contains => {
-                            *first_word.to_lowercase() != term.to_lowercase()
-                                && *last_word.to_lowercase() != term.to_lowercase()
-                        }
-                        SearchType::Suffix => *last_word.to_lowercase() == term.to_lowercase(),
-                        SearchType::Prefix => *first_word.to_lowercase() == term.to_lowercase(),
-                    };
-                    if condition {
-                        let priority = levenshtein(term, item);
-                        sorted_result.push((priority as u8, item.to_string()));
-                    }
-                }
-            } else {
-                let priority = levenshtein(term, item);
-                sorted_result.push((priority as u8, item.to_string()));
+    // Fold the post-filter exactly like the index that produced the results
+    let normalization = structure.normalization();
+    let folded_term = normalization.apply(term);
+    let term_length = term.chars().count();
+
+    // Unicode folding may change a key's length, so its order is not a bound
+    let by_length = structure.shortest_first(&query) && normalization != Normalization::UnicodeFold;
+
+    // Max-heap on distance, so the worst of the best MAX_RESULTS sits on top
+    let mut best: BinaryHeap<(usize, String)> = BinaryHeap::with_capacity(MAX_RESULTS + 1);
+
+    for item in candidates.filter_map(|id| strings.resolve(id)) {
+        // Edit distance is at least the length difference; once candidates
+        // only get longer, nothing further can beat a full heap
+        if by_length && best.len() == MAX_RESULTS {
+            let bound = item.chars().count().abs_diff(term_length);
+            if best.peek().is_some_and(|(worst, _)| bound > *worst) {
+                break;
             }
         }
+
+        if matches!(scope, Scope::Lines) {
+            let lines_scope = item.unicode_words().collect::<Vec<&str>>();
+            let (Some(first_word), Some(last_word)) = (lines_scope.first(), lines_scope.last()) else {
+                continue;
+            };
+            let first_word = normalization.apply(first_word);
+            let last_word = normalization.apply(last_word);
+            let condition = match search_type {
+                SearchType::Contains(mode) => mode.allows_ends() || (first_word != folded_term && last_word != folded_term),
+                SearchType::Suffix => last_word == folded_term,
+                SearchType::Prefix => first_word == folded_term,
+                // The same prefix match the trie makes for words
+                SearchType::Fuzzy => prefix_distance(&folded_term, &first_word) <= allowed_typos(term),
+                SearchType::Autocomplete
+                | SearchType::Range
+                | SearchType::Successor
+                | SearchType::Predecessor
+                | SearchType::Wildcard
+                | SearchType::Regex
+                | SearchType::Phrase
+                | SearchType::Similar => true,
+            };
+            if !condition {
+                continue;
+            }
+        }
+
+        let priority = levenshtein(term, item);
+        if best.len() < MAX_RESULTS {
+            best.push((priority, item.to_string()));
+        } else if best.peek().is_some_and(|(worst, worst_item)| (priority, item) < (*worst, worst_item.as_str())) {
+            best.pop();
+            best.push((priority, item.to_string()));
+        }
     }
 
-    sorted_result.sort();
-    sorted_result.truncate(100);
-    sorted_result
+    Ok(best.into_sorted_vec()
+        .into_iter()
+        .map(|(priority, item)| (u8::try_from(priority).unwrap_or(u8::MAX), item))
+        .collect())
 }
 
-pub fn load_index() -> Result<HashMap<String, SearchIndex>, String> {
-    let base_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-        .parent() // go one directory up
-        .ok_or("Failed to determine project root")?
-        .to_path_buf();
+#[derive(Default)]
+pub struct LoadOptions {
+    // Directory holding `word_scope` and `line_scope`, as passed to
+    // `compile --output`; `serialized_outputs` in the project when unset
+    pub index_dir: Option<PathBuf>,
+    // Load the FM-index rather than the suffix array when a scope has both
+    pub prefer_fm_index: bool,
+    // Load only the FSTs and string tables, as written by `compile --index
+    // fst`. Nothing but the string tables is decoded, so startup is
+    // quickest, but only prefix, suffix and range queries are answered
+    pub fst_only: bool,
+}
 
-    let paths = [
-        (   "Trie_Word",
-            "serialized_outputs/word_scope/trie-serial.bin"
-        ),
-        (   "Trie_Line",
-            "serialized_outputs/line_scope/trie-serial.bin"
-        ),
-        (
-            "SuffixTree_Word",
-            "serialized_outputs/word_scope/suffix-serial.bin",
-        ),
-        (
-            "SuffixTree_Line",
-            "serialized_outputs/line_scope/suffix-serial.bin",
-        ),
-        (
-            "NGramIndex_Word",
-            "serialized_outputs/word_scope/ngram-serial.bin",
-        ),
-        (
-            "NGramIndex_Line",
-            "serialized_outputs/line_scope/ngram-serial.bin",
-        ),
-    ];
+pub fn load_index() -> Result<HashMap<String, SearchIndex>, RuntimeError> {
+    load_index_with(&LoadOptions::default())
+}
+
+pub fn load_index_with(options: &LoadOptions) -> Result<HashMap<String, SearchIndex>, RuntimeError> {
+    let index_dir = match &options.index_dir {
+        Some(index_dir) => index_dir.clone(),
+        None => PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .parent() // go one directory up
+            .ok_or(RuntimeError::ProjectRoot)?
+            .join("serialized_outputs"),
+    };
 
     let mut result = HashMap::new();
 
-    for (key, relative_path) in paths.iter() {
-        let full_path = base_path.join(relative_path);
-        let contents =
-            fs::read(&full_path).map_err(|_| format!("Failed to read file: {:?}", full_path))?;
-        let decoded: SearchIndex = match *key {
-            "Trie_Word" | "Trie_Line" => {
-                let trie: Trie = bincode::decode_from_slice(&contents, config::standard())
-                    .map_err(|_| format!("Failed to decode trie: {:?}", full_path))?
-                    .0;
-                SearchIndex::Trie(trie)
+    // A build may cover one scope and only some indexes. A scope without a
+    // string table is skipped, as is any index file that was not built, and
+    // queries needing them report the index as missing
+    for (suffix, scope_path) in [("Word", "word_scope"), ("Line", "line_scope")] {
+        let scope_dir = index_dir.join(scope_path);
+
+        let strings_path = scope_dir.join("strings-serial.bin");
+        if !strings_path.exists() {
+            continue;
+        }
+        let contents = read(&strings_path)?;
+        let (strings, table): (StringTable, u64) = decode_versioned(&contents)
+            .map_err(|source| RuntimeError::Load { path: strings_path.clone(), source })?;
+        result.insert(format!("Strings_{}", suffix), SearchIndex::Strings(Arc::new(strings)));
+        // Every index's ids must point into this table
+        let check = |path: &Path, built_against: u64| {
+            if built_against == table {
+                Ok(())
+            } else {
+                Err(RuntimeError::StaleIndex { path: path.to_path_buf(), strings: strings_path.clone() })
             }
-            "SuffixTree_Word" | "SuffixTree_Line" => {
-                let suffix_tree: SuffixTree =
-                    bincode::decode_from_slice(&contents, config::standard())
-                        .map_err(|_| format!("Failed to decode suffix tree: {:?}", full_path))?
-                        .0;
-                SearchIndex::SuffixTree(suffix_tree)
+        };
+
+        // FSTs are only memory-mapped, not decoded
+        if scope_dir.join(trees::fst::FORWARD_FILE).exists() {
+            let fst = FstIndex::open(&scope_dir).map_err(|source| RuntimeError::Load { path: scope_dir.clone(), source })?;
+            check(&scope_dir.join(trees::fst::META_FILE), fst.table())?;
+            let fst: Arc<dyn Searchable> = Arc::new(fst);
+            result.insert(format!("Fst_{}", suffix), SearchIndex::Structure(fst));
+        }
+        if options.fst_only {
+            continue;
+        }
+
+        // The suffix array and the FM-index answer the same queries, so only
+        // one is loaded: the suffix array unless it is missing or the
+        // FM-index is preferred
+        let has_fm_index = scope_dir.join("fm-index-serial.bin").exists();
+        let use_fm_index = has_fm_index && (options.prefer_fm_index || !scope_dir.join("suffix-array-serial.bin").exists());
+
+        for (kind, file_name, load) in STRUCTURES.iter() {
+            let full_path = scope_dir.join(file_name);
+            let skipped = match *kind {
+                "SuffixArray" => use_fm_index,
+                "FmIndex" => !use_fm_index,
+                _ => false,
+            };
+            if skipped || !full_path.exists() {
+                continue;
             }
-            "NGramIndex_Word" | "NGramIndex_Line" => {
-                let ngram_index: NGramIndex =
-                    bincode::decode_from_slice(&contents, config::standard())
-                        .map_err(|_| format!("Failed to decode ngram: {:?}", full_path))?
-                        .0;
-                SearchIndex::NGramIndex(ngram_index)
+            let contents = read(&full_path)?;
+            let (structure, built_against) = load(&contents)
+                .map_err(|source| RuntimeError::Load { path: full_path.clone(), source })?;
+            check(&full_path, built_against)?;
+            result.insert(format!("{}_{}", kind, suffix), SearchIndex::Structure(structure));
+        }
+    }
+    if result.is_empty() {
+        return Err(RuntimeError::IndexMissing(format!("either scope under {:?}", index_dir)));
+    }
+
+    // Indexes of one scope must fold text the same way or strategies disagree
+    for scope in ["Word", "Line"] {
+        let mut policies = result
+            .iter()
+            .filter(|(key, _)| key.ends_with(scope))
+            .filter_map(|(_, index)| index.normalization());
+        if let Some(first) = policies.next() {
+            if let Some(other) = policies.find(|policy| *policy != first) {
+                return Err(RuntimeError::MixedNormalization {
+                    scope,
+                    first: first.name(),
+                    other: other.name(),
+                });
             }
-            _ => return Err(format!("Unknown key: {}", key)),
-        };
-        result.insert(key.to_string(), decoded);
+        }
     }
 
     Ok(result)
 }
+
+fn read(path: &Path) -> Result<Vec<u8>, RuntimeError> {
+    fs::read(path).map_err(|source| RuntimeError::Io { path: path.to_path_buf(), source })
+}
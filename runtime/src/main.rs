@@ -7,8 +7,10 @@ use std::{collections::HashMap, sync::mpsc::{channel, Receiver, Sender}};
 use std::{
     error::Error,
     io::{self, Stdout},
+    path::PathBuf,
     time::{Duration, Instant},
 };
+use clap::Parser;
 use throbber_widgets_tui::Throbber;
 use tui::{
     backend::CrosstermBackend,
@@ -19,7 +21,21 @@ use tui::{
     Frame, Terminal,
 };
 
-use runtime::{load_index, perform_search, AppMessage, Scope, SearchIndex, SearchType};  // Import from our lib
+use data_structs::normalize::Normalization;
+use runtime::{load_index_with, normalization_for, perform_search, AppMessage, ContainsMode, LoadOptions, Scope, SearchIndex, SearchType};  // Import from our lib
+
+#[derive(Parser)]
+#[command(name = "runtime", about = "Searches the indexes written by compile")]
+struct Args {
+    #[arg(value_name = "INDEX_DIR", default_value = "serialized_outputs", help = "Directory compile wrote its indexes to (its --output)")]
+    index_dir: PathBuf,
+
+    #[arg(long, help = "Answer contains queries with the FM-index when the suffix array was built too")]
+    fm_index: bool,
+
+    #[arg(long, help = "Load only the FSTs (compile --index fst): quickest to start, but only prefix, suffix and range queries work")]
+    fst_only: bool,
+}
 
 struct App {
     input_scope: String,
@@ -102,8 +118,14 @@ impl App {
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
     let start_time = Instant::now();
-    let indexes = load_index().unwrap(); // Load indexes before starting the TUI
+    let options = LoadOptions {
+        index_dir: Some(args.index_dir),
+        prefer_fm_index: args.fm_index,
+        fst_only: args.fst_only,
+    };
+    let indexes = load_index_with(&options)?; // Load indexes before starting the TUI
     let duration = start_time.elapsed();
     println!("time took to load all indexes {:?}",duration);
     let mut terminal = setup_terminal()?;
@@ -269,7 +291,7 @@ fn ui(frame: &mut Frame, app: &mut App) {
     // Type input
     let type_block = Block::default()
         .borders(Borders::ALL)
-        .title("Search Type (1: Prefix, 2: Suffix, 3: Contains)")
+        .title("Search Type (1: Prefix, 2: Suffix, 3: Contains, 4: Fuzzy, 5: Autocomplete, 6: Range, 7: Wildcard, 8: Regex, 9: Phrase, 10: Similar, 11: Strict Contains, 12: Successor, 13: Predecessor)")
         .style(match app.state {
             AppState::TypeInput => Style::default().fg(Color::Green),
             _ => Style::default(),
@@ -317,18 +339,18 @@ fn ui(frame: &mut Frame, app: &mut App) {
 
                 frame.render_stateful_widget(throbber, centered_area, &mut app.throbber_state);
             } else {
+                let scope = if app.input_scope.trim() == "2" { Scope::Lines } else { Scope::Words };
+                let normalization = normalization_for(&app.indexes, &scope);
                 let items: Vec<ListItem> = app
                     .results
                     .iter()
                     .enumerate()
                     .map(|(i, term)| {
                         let prefix = format!("#{} -> ", i + 1);
-                        let term_lower = term.to_lowercase();
-                        let search_term = app.input_term.trim().to_lowercase();
                         let is_selected = app.result_state.selected() == Some(i);
 
                         let line = if is_selected {
-                            if let Some(start_idx) = term_lower.find(&search_term) {
+                            if let Some((start_idx, end_idx)) = match_range(term, app.input_term.trim(), normalization) {
                                 Line::from(vec![
                                     Span::styled(prefix, Style::default().fg(Color::Green)),
                                     Span::styled(
@@ -336,11 +358,11 @@ fn ui(frame: &mut Frame, app: &mut App) {
                                         Style::default().fg(Color::Green),
                                     ),
                                     Span::styled(
-                                        &term[start_idx..start_idx + search_term.len()],
+                                        &term[start_idx..end_idx],
                                         Style::default().fg(Color::LightYellow),
                                     ),
                                     Span::styled(
-                                        &term[start_idx + search_term.len()..],
+                                        &term[end_idx..],
                                         Style::default().fg(Color::Green),
                                     ),
                                 ])
@@ -378,7 +400,7 @@ fn ui(frame: &mut Frame, app: &mut App) {
             let help_text = match app.state {
                 AppState::ScopeInput => "Enter 1 for Words or 2 for Lines, then press Enter",
                 AppState::TypeInput => {
-                    "Enter 1 for Prefix, 2 for Suffix, or 3 for Contains, then press Enter"
+                    "Enter 1 for Prefix, 2 for Suffix, 3 for Contains anywhere, 4 for Fuzzy, 5 for Autocomplete, 6 for Range (e.g. ma..me, or kite for the words after it), 7 for Wildcard (e.g. inter*tion), 8 for Regex (e.g. ^re.*ing$), 9 for Phrase in Lines (e.g. quick brown fox or quick NEAR/3 fox), 10 for Similar (e.g. acommodation), 11 for Contains strictly inside (not at the start or end), 12 for the word right after or 13 for the word right before the term, then press Enter"
                 }
                 AppState::TermInput => "Enter your search term and press Enter",
                 _ => "",
@@ -412,20 +434,39 @@ fn ui(frame: &mut Frame, app: &mut App) {
     frame.render_stateful_widget(debug_list, chunks[5], &mut app.debug_state);
 }
 
+// Byte range of the first match of `query` in `text`, compared the way the
+// index folds text but cut on `text`'s own char boundaries, since folding
+// may change a char's length
+fn match_range(text: &str, query: &str, normalization: Normalization) -> Option<(usize, usize)> {
+    let query = normalization.apply(query);
+    if query.is_empty() {
+        return None;
+    }
+    for (start, _) in text.char_indices() {
+        let mut folded = String::new();
+        for (offset, char) in text[start..].char_indices() {
+            folded.push_str(&normalization.apply(char.encode_utf8(&mut [0; 4])));
+            if folded == query {
+                return Some((start, start + offset + char.len_utf8()));
+            }
+            if !query.starts_with(folded.as_str()) {
+                break;
+            }
+        }
+    }
+    None
+}
+
 fn handle_scope_input(app: &mut App, key: KeyEvent) {
     match key.code {
         KeyCode::Enter => {
-            if app.input_scope.trim() == "1" || app.input_scope.trim() == "2" {
-                app.add_debug_message(format!(
-                    "Scope set to: {}",
-                    if app.input_scope.trim() == "1" {
-                        "Words"
-                    } else {
-                        "Lines"
-                    }
-                ));
-                app.state = AppState::TypeInput;
-            }
+            let scope = match app.input_scope.trim() {
+                "1" => "Words",
+                "2" => "Lines",
+                _ => return,
+            };
+            app.add_debug_message(format!("Scope set to: {}", scope));
+            app.state = AppState::TypeInput;
         }
         KeyCode::Char(c) => {
             app.input_scope.push(c);
@@ -440,22 +481,24 @@ fn handle_scope_input(app: &mut App, key: KeyEvent) {
 fn handle_type_input(app: &mut App, key: KeyEvent) {
     match key.code {
         KeyCode::Enter => {
-            if app.input_type.trim() == "1"
-                || app.input_type.trim() == "2"
-                || app.input_type.trim() == "3"
-            {
-                app.add_debug_message(format!(
-                    "Search type set to: {}",
-                    if app.input_type.trim() == "1" {
-                        "Prefix"
-                    } else if app.input_type.trim() == "2" {
-                        "Suffix"
-                    } else {
-                        "Contains"
-                    }
-                ));
-                app.state = AppState::TermInput;
-            }
+            let search_type = match app.input_type.trim() {
+                "1" => "Prefix",
+                "2" => "Suffix",
+                "3" => "Contains (anywhere)",
+                "4" => "Fuzzy",
+                "5" => "Autocomplete",
+                "6" => "Range",
+                "7" => "Wildcard",
+                "8" => "Regex",
+                "9" => "Phrase",
+                "10" => "Similar",
+                "11" => "Contains (strict infix)",
+                "12" => "Successor",
+                "13" => "Predecessor",
+                _ => return,
+            };
+            app.add_debug_message(format!("Search type set to: {}", search_type));
+            app.state = AppState::TermInput;
         }
         KeyCode::Char(c) => {
             app.input_type.push(c);
@@ -470,50 +513,69 @@ fn handle_type_input(app: &mut App, key: KeyEvent) {
 fn handle_term_input(app: &mut App, key: KeyEvent) {
     match key.code {
         KeyCode::Enter => {
-            if !app.input_term.trim().is_empty() {
-                // Set loading state and clear results immediately
-                app.is_loading = true;
-                app.loading_start_time = Some(Instant::now());
-                app.results.clear();
-                app.state = AppState::ShowResults;
-
-                app.add_debug_message(format!(
-                    "Searching for term: \x1b[1m{}\x1b[0m",
-                    app.input_term.trim()
-                ));
-
-                let scope = match app.input_scope.trim() {
-                    "1" => Scope::Words,
-                    "2" => Scope::Lines,
-                    _ => return,
-                };
+            if app.input_term.trim().is_empty() {
+                return;
+            }
+            // Set loading state and clear results immediately
+            app.is_loading = true;
+            app.loading_start_time = Some(Instant::now());
+            app.results.clear();
+            app.state = AppState::ShowResults;
+
+            app.add_debug_message(format!(
+                "Searching for term: \x1b[1m{}\x1b[0m",
+                app.input_term.trim()
+            ));
+
+            let scope = match app.input_scope.trim() {
+                "1" => Scope::Words,
+                "2" => Scope::Lines,
+                _ => return,
+            };
 
-                let search_type = match app.input_type.trim() {
-                    "1" => SearchType::Prefix,
-                    "2" => SearchType::Suffix,
-                    "3" => SearchType::Contains,
-                    _ => return,
-                };
+            let search_type = match app.input_type.trim() {
+                "1" => SearchType::Prefix,
+                "2" => SearchType::Suffix,
+                "3" => SearchType::Contains(ContainsMode::Anywhere),
+                "4" => SearchType::Fuzzy,
+                "5" => SearchType::Autocomplete,
+                "6" => SearchType::Range,
+                "7" => SearchType::Wildcard,
+                "8" => SearchType::Regex,
+                "9" => SearchType::Phrase,
+                "10" => SearchType::Similar,
+                "11" => SearchType::Contains(ContainsMode::StrictInfix),
+                "12" => SearchType::Successor,
+                "13" => SearchType::Predecessor,
+                _ => return,
+            };
 
-                // Clone all necessary data
-                let term = app.input_term.trim().to_string();
-                let scope_clone = scope;
-                let search_type_clone = search_type;
-                let app_sender = app.sender.clone();
-                let debug_sender = app.sender.clone();
-                let indexes = app.indexes.clone(); // Use preloaded indexes
-                let start_time = Instant::now();
-
-                // Perform search in a separate thread
-                std::thread::spawn(move || {
-                    let results =
-                        perform_search(&indexes, scope_clone, search_type_clone, &term, debug_sender);
-                    let duration = start_time.elapsed();
-                    app_sender
-                        .send(AppMessage::SearchComplete(results, duration))
-                        .unwrap();
-                });
-            }
+            // Clone all necessary data
+            let term = app.input_term.trim().to_string();
+            let scope_clone = scope;
+            let search_type_clone = search_type;
+            let app_sender = app.sender.clone();
+            let debug_sender = app.sender.clone();
+            let indexes = app.indexes.clone(); // Use preloaded indexes
+            let start_time = Instant::now();
+
+            // Perform search in a separate thread
+            std::thread::spawn(move || {
+                let results =
+                    match perform_search(&indexes, scope_clone, search_type_clone, &term, debug_sender.clone()) {
+                        Ok(results) => results,
+                        Err(e) => {
+                            if let Err(e) = debug_sender.send(AppMessage::Debug(format!("Search failed: {}", e))) {
+                                eprintln!("Failed to send debug message: {}", e);
+                            }
+                            Vec::new()
+                        }
+                    };
+                let duration = start_time.elapsed();
+                app_sender
+                    .send(AppMessage::SearchComplete(results, duration))
+                    .unwrap();
+            });
         }
         KeyCode::Char(c) => {
             app.input_term.push(c);
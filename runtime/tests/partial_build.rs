@@ -0,0 +1,63 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::channel;
+
+use compile::{build_scope, BuildOptions, Scope as BuildScope, Trees};
+use runtime::{load_index_with, perform_search, LoadOptions, RuntimeError, Scope, SearchType};
+
+// A fresh output directory per test, with a one-file dataset
+fn build(name: &str, trees: &[Trees], scopes: &[BuildScope]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("runtime-partial-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("input.txt");
+    fs::write(&input, "the quick brown fox\njumps over the lazy dog\n").unwrap();
+
+    let options = BuildOptions {
+        inputs: vec![input],
+        output_dir: Some(dir.join("indexes")),
+        ..BuildOptions::default()
+    };
+    for &scope in scopes.iter() {
+        build_scope(trees, scope, &options).unwrap();
+    }
+    dir
+}
+
+fn search(dir: &Path, scope: Scope, search_type: SearchType, term: &str) -> Result<Vec<String>, RuntimeError> {
+    let options = LoadOptions { index_dir: Some(dir.join("indexes")), ..LoadOptions::default() };
+    let index = load_index_with(&options)?;
+    let (sender, _receiver) = channel();
+    let results = perform_search(&index, scope, search_type, term, sender)?;
+    Ok(results.into_iter().map(|(_, item)| item).collect())
+}
+
+#[test]
+fn loads_a_build_of_some_indexes_in_one_scope() {
+    let dir = build("line", &[Trees::Trie, Trees::NGramIndex], &[BuildScope::Line]);
+
+    let found = search(&dir, Scope::Lines, SearchType::Prefix, "jumps").unwrap();
+    assert_eq!(found, ["jumps over the lazy dog"]);
+
+    // Neither the word scope nor the radix and suffix trees were built
+    assert!(matches!(search(&dir, Scope::Words, SearchType::Prefix, "qu"), Err(RuntimeError::IndexMissing(_))));
+    assert!(matches!(search(&dir, Scope::Lines, SearchType::Suffix, "dog"), Err(RuntimeError::IndexMissing(_))));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn loads_a_build_of_only_the_fm_index() {
+    let dir = build("fm", &[Trees::FmIndex], &[BuildScope::Word, BuildScope::Line]);
+
+    let found = search(&dir, Scope::Words, SearchType::Contains(Default::default()), "uic").unwrap();
+    assert_eq!(found, ["quick"]);
+    assert!(matches!(search(&dir, Scope::Words, SearchType::Fuzzy, "quick"), Err(RuntimeError::IndexMissing(_))));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn an_empty_directory_is_an_error() {
+    let dir = build("empty", &[], &[]);
+    assert!(matches!(search(&dir, Scope::Words, SearchType::Prefix, "qu"), Err(RuntimeError::IndexMissing(_))));
+    fs::remove_dir_all(&dir).unwrap();
+}